@@ -88,10 +88,12 @@ install -Dpm0644 -t %{buildroot}%{_sysconfdir}/%{pkgname} etc/greenboot/greenboo
 install -D -t %{buildroot}%{_prefix}/lib/bootupd/grub2-static/configs.d grub2/08_greenboot.cfg
 mkdir -p %{buildroot}%{_sysconfdir}/%{pkgname}/check/required.d
 mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/check/wanted.d
+mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/check/plugins.d
 mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/green.d
 mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/red.d
 mkdir -p %{buildroot}%{_prefix}/lib/%{pkgname}/check/required.d
 mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/check/wanted.d
+mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/check/plugins.d
 mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/green.d
 mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/red.d
 mkdir -p %{buildroot}%{_unitdir}
@@ -122,6 +124,7 @@ install -DpZm 0644 usr/lib/systemd/system/greenboot-healthcheck.service.d/10-net
 %{_libexecdir}/%{pkgname}/%{pkgname}
 %{_unitdir}/greenboot-healthcheck.service
 %{_unitdir}/greenboot-set-rollback-trigger.service
+%{_unitdir}/greenboot-mark-bad@.service
 %{_unitdir}/greenboot-success.target
 %config(noreplace) %{_sysconfdir}/%{pkgname}/greenboot.conf
 %{_prefix}/lib/bootupd/grub2-static/configs.d/08_greenboot.cfg
@@ -129,12 +132,14 @@ install -DpZm 0644 usr/lib/systemd/system/greenboot-healthcheck.service.d/10-net
 %dir %{_prefix}/lib/%{pkgname}/check
 %dir %{_prefix}/lib/%{pkgname}/check/required.d
 %dir %{_prefix}/lib/%{pkgname}/check/wanted.d
+%dir %{_prefix}/lib/%{pkgname}/check/plugins.d
 %dir %{_prefix}/lib/%{pkgname}/green.d
 %dir %{_prefix}/lib/%{pkgname}/red.d
 %dir %{_sysconfdir}/%{pkgname}
 %dir %{_sysconfdir}/%{pkgname}/check
 %dir %{_sysconfdir}/%{pkgname}/check/required.d
 %dir %{_sysconfdir}/%{pkgname}/check/wanted.d
+%dir %{_sysconfdir}/%{pkgname}/check/plugins.d
 %dir %{_sysconfdir}/%{pkgname}/green.d
 %dir %{_sysconfdir}/%{pkgname}/red.d
 
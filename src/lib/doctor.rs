@@ -0,0 +1,528 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use glob::glob;
+use serde_json::{Value, json};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::greenboot::{CheckKind, install_paths, list_checks_in};
+use crate::grub::validate_grubenv;
+use crate::handler::{detect_os_deployment, rollback_plan};
+use crate::mount::{find_mount_point, is_mount_rw};
+use crate::store::BootStateStore;
+use crate::sysroot::{has_sysroot, rooted, sysroot};
+
+/// grub.cfg locations, in the order GRUB reads them on BIOS and EFI systems
+static GRUB_CFG_CANDIDATES: [&str; 2] = ["/boot/grub2/grub.cfg", "/boot/grub/grub.cfg"];
+
+/// units greenboot needs enabled, and whether a disabled one breaks it
+static GREENBOOT_UNITS: [(&str, bool); 2] = [
+    ("greenboot-healthcheck.service", true),
+    ("greenboot-set-rollback-trigger.service", false),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// how bad a doctor finding is
+pub enum Severity {
+    Pass,
+    /// greenboot works, but not as well as it could
+    Warn,
+    /// greenboot cannot protect the boot
+    Fail,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Pass => "pass",
+            Severity::Warn => "warn",
+            Severity::Fail => "fail",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// outcome of a single `greenboot doctor` test
+pub struct Finding {
+    /// what was tested, e.g. grub.cfg or units
+    pub check: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// how to fix a warning or failure
+    pub hint: Option<String>,
+}
+
+impl Finding {
+    pub fn pass(check: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            severity: Severity::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warn(check: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            check,
+            severity: Severity::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    pub fn fail(check: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            check,
+            severity: Severity::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "check": self.check,
+            "severity": self.severity.as_str(),
+            "message": self.message,
+            "hint": self.hint,
+        })
+    }
+}
+
+/// Tests the integration of greenboot with the bootloader, systemd and the
+/// deployment manager. Checks that only make sense on the running system are
+/// left out with a sysroot set.
+pub fn doctor(bootloader: &dyn BootStateStore, disabled: &[String]) -> Vec<Finding> {
+    let mut findings = vec![];
+    if bootloader.name() == "grub" {
+        let candidates: Vec<PathBuf> = GRUB_CFG_CANDIDATES.iter().map(rooted).collect();
+        findings.push(check_grub_cfg_in(&candidates));
+        if let Some(grubenv) = bootloader.storage_path() {
+            findings.push(check_grubenv(&grubenv));
+        }
+    } else {
+        findings.push(match bootloader.verify() {
+            Ok(()) => Finding::pass(
+                "boot state",
+                format!("{} boot state is usable", bootloader.name()),
+            ),
+            Err(e) => Finding::fail(
+                "boot state",
+                format!("{} boot state is not usable: {e:#}", bootloader.name()),
+                "check GREENBOOT_BOOTLOADER and its path settings in greenboot.conf",
+            ),
+        });
+    }
+    if has_sysroot() {
+        log::info!(
+            "Working on {}; skipping mount, unit and deployment checks",
+            sysroot().display()
+        );
+    } else {
+        findings.extend(check_boot_mount(bootloader));
+        findings.extend(
+            GREENBOOT_UNITS
+                .iter()
+                .map(|(unit, required)| check_unit(unit, *required)),
+        );
+        findings.push(check_deployment());
+    }
+    findings.extend(check_dirs_in(&install_paths(), disabled));
+    findings
+}
+
+/// tests that the first grub.cfg found sources greenboot's boot counting
+fn check_grub_cfg_in(candidates: &[PathBuf]) -> Finding {
+    let Some(cfg) = candidates.iter().find(|path| path.is_file()) else {
+        return Finding::warn(
+            "grub.cfg",
+            "no grub.cfg found, cannot tell whether GRUB counts boot attempts",
+            "check that GRUB is the bootloader or set GREENBOOT_BOOTLOADER",
+        );
+    };
+    let text = match grub_cfg_text(cfg) {
+        Ok(text) => text,
+        Err(e) => {
+            return Finding::fail(
+                "grub.cfg",
+                format!("cannot read {}: {e}", cfg.display()),
+                "run greenboot doctor as root",
+            );
+        }
+    };
+    if !text.contains("boot_counter") {
+        return Finding::fail(
+            "grub.cfg",
+            format!(
+                "{} does not include 08_greenboot.cfg, GRUB will not count boot attempts",
+                cfg.display()
+            ),
+            "run `bootupctl update` to install configs.d/08_greenboot.cfg",
+        );
+    }
+    if !text.contains("insmod increment") {
+        return Finding::fail(
+            "grub.cfg",
+            format!(
+                "{} does not load the increment module, GRUB cannot decrement boot_counter",
+                cfg.display()
+            ),
+            "run `bootupctl update` to install the current 08_greenboot.cfg",
+        );
+    }
+    if let Some(dir) = cfg.parent().filter(|dir| lacks_increment_module(dir)) {
+        return Finding::fail(
+            "grub.cfg",
+            format!(
+                "increment.mod is missing from the GRUB modules in {}",
+                dir.display()
+            ),
+            "reinstall the GRUB modules package for this platform",
+        );
+    }
+    Finding::pass(
+        "grub.cfg",
+        format!("{} includes greenboot boot counting", cfg.display()),
+    )
+}
+
+/// content of `cfg` and, if it sources them, the snippets in configs.d
+fn grub_cfg_text(cfg: &Path) -> std::io::Result<String> {
+    let mut text = fs::read_to_string(cfg)?;
+    if text.contains("configs.d") {
+        let configs = cfg.with_file_name("configs.d");
+        let pattern = glob::Pattern::escape(&configs.to_string_lossy());
+        for snippet in glob(&format!("{pattern}/*.cfg"))
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            text.push_str(&fs::read_to_string(snippet)?);
+        }
+    }
+    Ok(text)
+}
+
+/// true if `grub_dir` holds GRUB modules, but not increment.mod. EFI images
+/// have their modules built in and no module directory.
+fn lacks_increment_module(grub_dir: &Path) -> bool {
+    let exists = |module: &str| {
+        let pattern = glob::Pattern::escape(&grub_dir.to_string_lossy());
+        glob(&format!("{pattern}/*/{module}"))
+            .into_iter()
+            .flatten()
+            .any(|path| path.is_ok())
+    };
+    exists("normal.mod") && !exists("increment.mod")
+}
+
+/// tests that the grubenv is a well formed, writable block
+fn check_grubenv(path: &Path) -> Finding {
+    match validate_grubenv(path) {
+        Err(e) => Finding::fail(
+            "grubenv",
+            e.to_string(),
+            format!("create it with `grub2-editenv {} create`", path.display()),
+        ),
+        Ok(status) if !status.is_ok() => Finding::fail(
+            "grubenv",
+            format!("{} is corrupt: {status}", path.display()),
+            "run `greenboot repair-grubenv`",
+        ),
+        Ok(_) => match fs::metadata(path) {
+            Ok(metadata) if metadata.permissions().readonly() => Finding::fail(
+                "grubenv",
+                format!("{} is read-only", path.display()),
+                format!("chmod u+w {}", path.display()),
+            ),
+            _ => Finding::pass("grubenv", format!("{} is valid", path.display())),
+        },
+    }
+}
+
+/// reports the state of the filesystem greenboot writes the boot state to
+fn check_boot_mount(bootloader: &dyn BootStateStore) -> Option<Finding> {
+    let path = bootloader.storage_path()?;
+    let mount_point = match find_mount_point(&path) {
+        Ok(mount_point) => mount_point,
+        Err(e) => {
+            return Some(Finding::warn(
+                "mount",
+                format!("cannot find the mount point of {}: {e}", path.display()),
+                "check that /proc/mounts is readable",
+            ));
+        }
+    };
+    Some(match is_mount_rw(&mount_point) {
+        Ok(true) => Finding::pass("mount", format!("{} is mounted rw", mount_point.display())),
+        Ok(false) => Finding::pass(
+            "mount",
+            format!(
+                "{} is mounted ro, greenboot remounts it rw to update the boot state",
+                mount_point.display()
+            ),
+        ),
+        Err(e) => Finding::fail(
+            "mount",
+            format!("cannot tell whether {} is rw: {e}", mount_point.display()),
+            "check that /proc/mounts is readable",
+        ),
+    })
+}
+
+/// true for `systemctl is-enabled` states that start the unit
+fn is_enabled_state(state: &str) -> bool {
+    matches!(
+        state,
+        "enabled" | "enabled-runtime" | "static" | "alias" | "generated" | "transient"
+    )
+}
+
+/// tests that `unit` starts on boot
+fn check_unit(unit: &str, required: bool) -> Finding {
+    let state = match Command::new("systemctl")
+        .args(["is-enabled", unit])
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        Err(e) => {
+            return Finding::warn(
+                "units",
+                format!("cannot run systemctl: {e}"),
+                "check that systemd is installed",
+            );
+        }
+    };
+    if is_enabled_state(&state) {
+        return Finding::pass("units", format!("{unit} is {state}"));
+    }
+    let state = if state.is_empty() { "unknown" } else { &state };
+    let message = format!("{unit} is {state}");
+    let hint = format!("systemctl enable {unit}");
+    if required {
+        Finding::fail("units", message, hint)
+    } else {
+        Finding::warn("units", message, hint)
+    }
+}
+
+/// tests that a failed boot can be rolled back
+fn check_deployment() -> Finding {
+    let Some(manager) = detect_os_deployment() else {
+        return Finding::warn(
+            "deployment",
+            "neither bootc nor rpm-ostree manages this system, failed boots cannot be rolled back",
+            "greenboot rolls back bootc and rpm-ostree deployments only",
+        );
+    };
+    match rollback_plan() {
+        Ok(plan) => Finding::pass(
+            "deployment",
+            format!("{manager} can roll back to {}", plan.target),
+        ),
+        Err(e) => Finding::warn(
+            "deployment",
+            format!("{manager} cannot roll back: {e:#}"),
+            "a rollback deployment exists after the first update",
+        ),
+    }
+}
+
+/// tests the check directories and the entries greenboot would skip
+fn check_dirs_in(install_paths: &[impl AsRef<Path>], disabled: &[String]) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut found_check_dir = false;
+    for install_path in install_paths {
+        for kind in CheckKind::ALL {
+            let dir = install_path.as_ref().join(kind.dir());
+            let Ok(metadata) = fs::metadata(&dir) else {
+                continue;
+            };
+            if !metadata.is_dir() {
+                findings.push(Finding::fail(
+                    "checks",
+                    format!("{} is not a directory", dir.display()),
+                    format!("replace {} with a directory", dir.display()),
+                ));
+                continue;
+            }
+            found_check_dir |= kind.is_check();
+            if metadata.permissions().mode() & 0o002 != 0 {
+                findings.push(Finding::fail(
+                    "checks",
+                    format!(
+                        "{} is world-writable, anyone can add checks run as root",
+                        dir.display()
+                    ),
+                    format!("chmod o-w {}", dir.display()),
+                ));
+            }
+        }
+    }
+    if !found_check_dir {
+        findings.push(Finding::warn(
+            "checks",
+            "no check/required.d or check/wanted.d directory found",
+            "add checks to /etc/greenboot/check/required.d",
+        ));
+    }
+
+    let inventory = list_checks_in(install_paths, disabled);
+    for entry in &inventory.entries {
+        let path = entry.path.display();
+        let world_writable = !entry.masked
+            && fs::metadata(&entry.path)
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o002 != 0);
+        if world_writable {
+            findings.push(Finding::fail(
+                "checks",
+                format!("{path} is world-writable, anyone can change what runs as root"),
+                format!("chmod o-w {path}"),
+            ));
+        }
+        if !entry.disabled && entry.lacks_exec_bit() {
+            findings.push(Finding::warn(
+                "checks",
+                format!("{path} lacks the exec bit and is skipped"),
+                format!("chmod +x {path}"),
+            ));
+        }
+    }
+    for name in &inventory.unmatched_disabled {
+        findings.push(Finding::warn(
+            "checks",
+            format!("DISABLED_HEALTHCHECKS names {name}, which matches no check"),
+            "fix the name in DISABLED_HEALTHCHECKS or remove it",
+        ));
+    }
+    for problem in &inventory.problems {
+        findings.push(Finding::fail(
+            "checks",
+            problem.clone(),
+            "fix the After= and Requires= metadata of the checks",
+        ));
+    }
+
+    if findings.is_empty() {
+        let runs = inventory.entries.iter().filter(|e| e.will_run()).count();
+        findings.push(Finding::pass(
+            "checks",
+            format!(
+                "{} checks and hooks found, {runs} will run",
+                inventory.entries.len()
+            ),
+        ));
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    static GREENBOOT_CFG: &str = include_str!("../../grub2/08_greenboot.cfg");
+
+    #[test]
+    fn test_check_grub_cfg() {
+        let root = tempdir().unwrap();
+        let grub_dir = root.path().join("grub2");
+        let cfg = grub_dir.join("grub.cfg");
+        let candidates = vec![root.path().join("grub/grub.cfg"), cfg.clone()];
+        assert_eq!(check_grub_cfg_in(&candidates).severity, Severity::Warn);
+
+        fs::create_dir_all(grub_dir.join("configs.d")).unwrap();
+        fs::write(&cfg, "source ${prefix}/configs.d/10_blscfg.cfg\n").unwrap();
+        assert_eq!(check_grub_cfg_in(&candidates).severity, Severity::Fail);
+
+        fs::write(grub_dir.join("configs.d/08_greenboot.cfg"), GREENBOOT_CFG).unwrap();
+        assert_eq!(check_grub_cfg_in(&candidates).severity, Severity::Pass);
+
+        fs::write(
+            grub_dir.join("configs.d/08_greenboot.cfg"),
+            GREENBOOT_CFG.replace("insmod increment", ""),
+        )
+        .unwrap();
+        assert_eq!(check_grub_cfg_in(&candidates).severity, Severity::Fail);
+    }
+
+    #[test]
+    fn test_check_grub_cfg_increment_module() {
+        let root = tempdir().unwrap();
+        let cfg = root.path().join("grub.cfg");
+        fs::write(&cfg, GREENBOOT_CFG).unwrap();
+        fs::create_dir(root.path().join("i386-pc")).unwrap();
+        fs::write(root.path().join("i386-pc/normal.mod"), "").unwrap();
+        let finding = check_grub_cfg_in(std::slice::from_ref(&cfg));
+        assert_eq!(finding.severity, Severity::Fail);
+        assert!(finding.message.contains("increment.mod"));
+
+        fs::write(root.path().join("i386-pc/increment.mod"), "").unwrap();
+        assert_eq!(check_grub_cfg_in(&[cfg]).severity, Severity::Pass);
+    }
+
+    #[test]
+    fn test_check_grubenv() {
+        let root = tempdir().unwrap();
+        let grubenv = root.path().join("grubenv");
+        assert_eq!(check_grubenv(&grubenv).severity, Severity::Fail);
+
+        fs::copy("testing_assets/grubenv", &grubenv).unwrap();
+        assert_eq!(check_grubenv(&grubenv).severity, Severity::Pass);
+
+        let mut permissions = fs::metadata(&grubenv).unwrap().permissions();
+        permissions.set_mode(0o444);
+        fs::set_permissions(&grubenv, permissions).unwrap();
+        assert_eq!(check_grubenv(&grubenv).severity, Severity::Fail);
+
+        fs::remove_file(&grubenv).unwrap();
+        fs::write(&grubenv, "# GRUB Environment Block\n").unwrap();
+        let finding = check_grubenv(&grubenv);
+        assert_eq!(finding.severity, Severity::Fail);
+        assert!(finding.message.contains("corrupt"));
+    }
+
+    #[test]
+    fn test_check_dirs() {
+        let root = tempdir().unwrap();
+        let install_path = root.path().join("etc/greenboot");
+        let findings = check_dirs_in(&[&install_path], &[]);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warn);
+
+        let required = install_path.join("check/required.d");
+        fs::create_dir_all(&required).unwrap();
+        fs::write(required.join("01_ok.sh"), "exit 0").unwrap();
+        let findings = check_dirs_in(&[&install_path], &[]);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Pass);
+
+        fs::write(required.join("02_binary"), "").unwrap();
+        fs::set_permissions(&required, fs::Permissions::from_mode(0o777)).unwrap();
+        let findings = check_dirs_in(&[&install_path], &[String::from("03_typo.sh")]);
+        let severities: Vec<Severity> = findings.iter().map(|f| f.severity).collect();
+        assert_eq!(
+            severities,
+            vec![Severity::Fail, Severity::Warn, Severity::Warn]
+        );
+    }
+
+    #[test]
+    fn test_is_enabled_state() {
+        assert!(is_enabled_state("enabled"));
+        assert!(is_enabled_state("static"));
+        assert!(!is_enabled_state("disabled"));
+        assert!(!is_enabled_state("masked"));
+        assert!(!is_enabled_state(""));
+    }
+}
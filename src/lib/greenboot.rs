@@ -1,20 +1,1723 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use config::{Config, File, FileFormat};
 use glob::glob;
-use std::collections::HashSet;
+use nix::sys::signal::{SigSet, SigmaskHow, Signal, killpg};
+use nix::unistd::{Pid, getpgrp, tcgetpgrp, tcsetpgrp};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read};
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
-use std::process::Command;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::handler::detect_os_deployment;
+use crate::sysroot::rooted;
 
 /// dir that greenboot looks for the health check and other scripts
 static GREENBOOT_INSTALL_PATHS: [&str; 2] = ["/usr/lib/greenboot", "/etc/greenboot"];
 
+/// how long a check or hook may run unless configured otherwise
+pub static DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// how many wanted or independent required checks run at once by default
+pub static DEFAULT_CHECK_PARALLELISM: usize = 4;
+
+/// exit code of a check that does not apply, as used by automake and systemd
+pub static SKIP_EXIT_CODE: i32 = 77;
+
+/// environment variable naming the result channel of a check, see [`CheckReport`]
+pub static RESULT_FILE_ENV: &str = "GREENBOOT_RESULT_FILE";
+
+/// install paths below the sysroot, in the order their scripts run
+pub(crate) fn install_paths() -> Vec<PathBuf> {
+    GREENBOOT_INSTALL_PATHS.iter().map(rooted).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// the directories greenboot runs entries from
+pub enum CheckKind {
+    /// check/required.d, a failure marks the boot bad
+    Required,
+    /// check/plugins.d, monitoring plugins whose CRITICAL marks the boot bad
+    Plugin,
+    /// check/wanted.d, failures are only logged
+    Wanted,
+    /// green.d, run after a passed health check
+    Green,
+    /// red.d, run after a failed health check
+    Red,
+}
+
+impl CheckKind {
+    /// all kinds, in the order greenboot runs them
+    pub const ALL: [CheckKind; 5] = [
+        CheckKind::Required,
+        CheckKind::Plugin,
+        CheckKind::Wanted,
+        CheckKind::Green,
+        CheckKind::Red,
+    ];
+
+    /// name used in logs and output
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckKind::Required => "required",
+            CheckKind::Plugin => "plugin",
+            CheckKind::Wanted => "wanted",
+            CheckKind::Green => "green",
+            CheckKind::Red => "red",
+        }
+    }
+
+    /// directory of this kind below an install path
+    pub fn dir(self) -> &'static str {
+        match self {
+            CheckKind::Required => "check/required.d",
+            CheckKind::Plugin => "check/plugins.d",
+            CheckKind::Wanted => "check/wanted.d",
+            CheckKind::Green => "green.d",
+            CheckKind::Red => "red.d",
+        }
+    }
+
+    /// true for health checks, only those can be disabled
+    pub fn is_check(self) -> bool {
+        matches!(
+            self,
+            CheckKind::Required | CheckKind::Plugin | CheckKind::Wanted
+        )
+    }
+
+    /// true if a failure marks the boot bad and skips the remaining checks
+    pub fn fails_boot(self) -> bool {
+        matches!(self, CheckKind::Required | CheckKind::Plugin)
+    }
+}
+
+impl fmt::Display for CheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// how a check or hook ended
+pub enum CheckStatus {
+    Passed,
+    /// failed or a check it requires did not succeed
+    Failed,
+    /// killed for running longer than its timeout
+    TimedOut,
+    /// exited with [`SKIP_EXIT_CODE`] or a condition is not met
+    Skipped,
+    /// listed in DISABLED_HEALTHCHECKS, not run
+    Disabled,
+    /// a plugin reported WARNING, logged like a failed wanted check
+    Warning,
+}
+
+impl CheckStatus {
+    pub const ALL: [CheckStatus; 6] = [
+        CheckStatus::Passed,
+        CheckStatus::Failed,
+        CheckStatus::TimedOut,
+        CheckStatus::Skipped,
+        CheckStatus::Disabled,
+        CheckStatus::Warning,
+    ];
+
+    /// name used in logs and output
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckStatus::Passed => "passed",
+            CheckStatus::Failed => "failed",
+            CheckStatus::TimedOut => "timed-out",
+            CheckStatus::Skipped => "skipped",
+            CheckStatus::Disabled => "disabled",
+            CheckStatus::Warning => "warning",
+        }
+    }
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// outcome of a single check or hook
+pub struct CheckResult {
+    pub name: String,
+    pub kind: CheckKind,
+    pub status: CheckStatus,
+    /// why the check failed or was skipped, the status line of plugins
+    pub message: Option<String>,
+    /// runs including retries, 0 if it did not run
+    pub attempts: u32,
+    /// metrics reported by plugins or through the result channel
+    pub perfdata: Vec<PerfData>,
+    /// how to fix the check, reported through the result channel
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn new(
+        entry: &CheckEntry,
+        status: CheckStatus,
+        message: Option<String>,
+        attempts: u32,
+    ) -> Self {
+        Self {
+            name: entry.name.clone(),
+            kind: entry.kind,
+            status,
+            message,
+            attempts,
+            perfdata: vec![],
+            hint: None,
+        }
+    }
+
+    /// takes over what the check reported through its result channel
+    fn merge(&mut self, report: CheckReport) {
+        if report.message.is_some() {
+            self.message = report.message;
+        }
+        self.perfdata.extend(report.metrics);
+        if report.hint.is_some() {
+            self.hint = report.hint;
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "kind": self.kind.as_str(),
+            "status": self.status.as_str(),
+            "message": self.message,
+            "attempts": self.attempts,
+            "perfdata": self.perfdata.iter().map(PerfData::to_json).collect::<Vec<_>>(),
+            "hint": self.hint,
+        })
+    }
+
+    /// parses the JSON representation, none if required fields are missing
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let string = |key: &str| value.get(key).and_then(Value::as_str);
+        let kind = string("kind")?;
+        let status = string("status")?;
+        Some(Self {
+            name: string("name")?.to_string(),
+            kind: CheckKind::ALL.into_iter().find(|k| k.as_str() == kind)?,
+            status: CheckStatus::ALL
+                .into_iter()
+                .find(|s| s.as_str() == status)?,
+            message: string("message").map(String::from),
+            attempts: value.get("attempts").and_then(Value::as_u64).unwrap_or(0) as u32,
+            perfdata: value
+                .get("perfdata")
+                .and_then(Value::as_array)
+                .map(|perfdata| perfdata.iter().filter_map(PerfData::from_json).collect())
+                .unwrap_or_default(),
+            hint: string("hint").map(String::from),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// What a check wrote to its result channel, the file named by
+/// GREENBOOT_RESULT_FILE in its environment. Every line is a JSON object
+/// like `{"status": "warning", "message": "...", "metrics": {"free_mb": 310},
+/// "hint": "..."}`, later lines override the status, message and hint of
+/// earlier ones and add to their metrics.
+pub struct CheckReport {
+    /// replaces the status of a check that exited successfully
+    pub status: Option<CheckStatus>,
+    pub message: Option<String>,
+    pub metrics: Vec<PerfData>,
+    pub hint: Option<String>,
+}
+
+impl CheckReport {
+    /// reads the result channel at `path` of `entry`, invalid lines are
+    /// logged and ignored
+    fn read(path: &Path, entry: &Path) -> Self {
+        let raw = fs::read_to_string(path).unwrap_or_default();
+        let (report, problems) = Self::parse(&raw);
+        for problem in problems {
+            log::warn!("ignoring result of {}: {problem}", entry.display());
+        }
+        report
+    }
+
+    fn parse(raw: &str) -> (Self, Vec<String>) {
+        let mut report = Self::default();
+        let mut problems = vec![];
+        for line in raw.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let value = match serde_json::from_str::<Value>(line) {
+                Ok(value) if value.is_object() => value,
+                _ => {
+                    problems.push(format!("not a JSON object: {line}"));
+                    continue;
+                }
+            };
+            if let Some(status) = value.get("status") {
+                let reported = status.as_str().and_then(|status| {
+                    CheckStatus::ALL
+                        .into_iter()
+                        .filter(|s| !matches!(s, CheckStatus::Disabled | CheckStatus::TimedOut))
+                        .find(|s| s.as_str() == status)
+                });
+                match reported {
+                    Some(reported) => report.status = Some(reported),
+                    None => problems.push(format!("invalid status {status}")),
+                }
+            }
+            if let Some(message) = value.get("message").and_then(Value::as_str) {
+                report.message = Some(message.to_string());
+            }
+            if let Some(hint) = value.get("hint").and_then(Value::as_str) {
+                report.hint = Some(hint.to_string());
+            }
+            let Some(metrics) = value.get("metrics") else {
+                continue;
+            };
+            let Some(metrics) = metrics.as_object() else {
+                problems.push(format!("metrics are no object: {metrics}"));
+                continue;
+            };
+            for (label, metric) in metrics {
+                // a bare number or an object with the fields of PerfData
+                let parsed = match metric {
+                    Value::Number(_) => {
+                        PerfData::from_json(&json!({"label": label, "value": metric}))
+                    }
+                    Value::Object(fields) => {
+                        let mut fields = fields.clone();
+                        fields.insert(String::from("label"), json!(label));
+                        PerfData::from_json(&Value::Object(fields))
+                    }
+                    _ => None,
+                };
+                match parsed {
+                    Some(parsed) => report.metrics.push(parsed),
+                    None => problems.push(format!("invalid metric {label}: {metric}")),
+                }
+            }
+        }
+        (report, problems)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A metric reported by a monitoring plugin after the `|` of its output,
+/// `'label'=value[UOM];[warn];[crit];[min];[max]`
+pub struct PerfData {
+    pub label: String,
+    /// none if the plugin reported `U`, a value it could not determine
+    pub value: Option<f64>,
+    pub unit: String,
+    /// warning range as given, e.g. `10:20` or `@5`
+    pub warn: Option<String>,
+    /// critical range as given
+    pub crit: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl PerfData {
+    /// parses the part after `label=`, none if the value is no number
+    fn parse(label: &str, fields: &str) -> Option<Self> {
+        let mut fields = fields.split(';');
+        let raw = fields.next()?;
+        let split = raw
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+            .unwrap_or(raw.len());
+        let (value, unit) = match raw.split_at(split) {
+            ("", "U") => (None, ""),
+            (number, unit) => (Some(number.parse().ok()?), unit),
+        };
+        let mut range = || fields.next().filter(|f| !f.is_empty()).map(String::from);
+        let (warn, crit) = (range(), range());
+        let mut bound = || fields.next().and_then(|f| f.parse().ok());
+        let (min, max) = (bound(), bound());
+        Some(Self {
+            label: label.to_string(),
+            value,
+            unit: unit.to_string(),
+            warn,
+            crit,
+            min,
+            max,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "label": self.label,
+            "value": self.value,
+            "unit": self.unit,
+            "warn": self.warn,
+            "crit": self.crit,
+            "min": self.min,
+            "max": self.max,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let string = |key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+        let number = |key: &str| value.get(key).and_then(Value::as_f64);
+        Some(Self {
+            label: string("label")?,
+            value: number("value"),
+            unit: string("unit").unwrap_or_default(),
+            warn: string("warn"),
+            crit: string("crit"),
+            min: number("min"),
+            max: number("max"),
+        })
+    }
+}
+
+/// Parses the space separated perfdata of plugin output, labels with spaces
+/// are quoted. Malformed metrics are left out.
+pub fn parse_perfdata(raw: &str) -> Vec<PerfData> {
+    let mut perfdata = vec![];
+    let mut rest = raw.trim_start();
+    while !rest.is_empty() {
+        let (label, after) = match rest.strip_prefix('\'') {
+            Some(quoted) => match quoted.split_once("'=") {
+                Some(split) => split,
+                None => break,
+            },
+            None => match rest.split_once('=') {
+                Some(split) => split,
+                None => break,
+            },
+        };
+        let (fields, next) = after.split_at(after.find(char::is_whitespace).unwrap_or(after.len()));
+        perfdata.extend(PerfData::parse(label, fields));
+        rest = next.trim_start();
+    }
+    perfdata
+}
+
+/// Splits the output of a monitoring plugin into its first line without the
+/// perfdata and the perfdata of all lines, long output may continue it
+/// after another `|`
+pub fn parse_plugin_output(stdout: &str) -> (Option<String>, Vec<PerfData>) {
+    let (first, long) = stdout.split_once('\n').unwrap_or((stdout, ""));
+    let (text, perf) = first.split_once('|').unwrap_or((first, ""));
+    let mut perfdata = parse_perfdata(perf);
+    if let Some((_, more)) = long.split_once('|') {
+        perfdata.extend(parse_perfdata(more));
+    }
+    let text = text.trim();
+    ((!text.is_empty()).then(|| text.to_string()), perfdata)
+}
+
+/// status of a monitoring plugin that exited with `code`, UNKNOWN and
+/// undefined codes map to `unknown`
+fn plugin_status(code: Option<i32>, unknown: CheckStatus) -> CheckStatus {
+    match code {
+        Some(0) => CheckStatus::Passed,
+        Some(1) => CheckStatus::Warning,
+        Some(2) => CheckStatus::Failed,
+        _ => unknown,
+    }
+}
+
+/// status of any other check or hook that exited with `code`
+fn exit_status(code: Option<i32>) -> CheckStatus {
+    match code {
+        Some(0) => CheckStatus::Passed,
+        Some(code) if code == SKIP_EXIT_CODE => CheckStatus::Skipped,
+        _ => CheckStatus::Failed,
+    }
+}
+
+/// Status of a `kind` entry that exited with `code`. A passing entry may
+/// report another status through its result channel, a failing one cannot
+/// turn into a pass.
+fn finished_status(
+    kind: CheckKind,
+    code: Option<i32>,
+    plugin_unknown: CheckStatus,
+    report: &CheckReport,
+) -> CheckStatus {
+    let status = match kind {
+        CheckKind::Plugin => plugin_status(code, plugin_unknown),
+        _ => exit_status(code),
+    };
+    match (status, report.status) {
+        (CheckStatus::Passed, Some(reported)) => reported,
+        _ => status,
+    }
+}
+
+/// Parses GREENBOOT_PLUGIN_UNKNOWN, what a plugin reporting UNKNOWN counts as:
+/// `warning`, `critical` or `skip`
+pub fn parse_plugin_unknown(raw: &str) -> Option<CheckStatus> {
+    match raw.trim().to_lowercase().as_str() {
+        "warning" => Some(CheckStatus::Warning),
+        "critical" => Some(CheckStatus::Failed),
+        "skip" => Some(CheckStatus::Skipped),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// outcome of the required, plugin and wanted checks, see [`run_diagnostics`]
+pub struct Diagnostics {
+    /// required, plugin then wanted checks in run order, up to the failure
+    /// that marked the boot bad
+    pub results: Vec<CheckResult>,
+    /// DISABLED_HEALTHCHECKS names that match no check
+    pub missing_disabled: Vec<String>,
+}
+
+impl Diagnostics {
+    /// names of the checks that ended with `status`
+    pub fn names(&self, status: CheckStatus) -> Vec<&str> {
+        names_with(&self.results, status)
+    }
+
+    /// one line each for failed, warning and skipped checks, as shown in the motd
+    pub fn summary(&self) -> String {
+        summarize(&self.results)
+    }
+}
+
+/// names of the `results` that ended with `status`
+pub fn names_with(results: &[CheckResult], status: CheckStatus) -> Vec<&str> {
+    results
+        .iter()
+        .filter(|result| result.status == status)
+        .map(|result| result.name.as_str())
+        .collect()
+}
+
+/// one line each for failed, timed out, warning and skipped checks in
+/// `results`, empty if none
+pub fn summarize(results: &[CheckResult]) -> String {
+    let mut lines = vec![];
+    for kind in [CheckKind::Required, CheckKind::Plugin, CheckKind::Wanted] {
+        for (status, label) in [
+            (CheckStatus::Failed, "Failed"),
+            (CheckStatus::TimedOut, "Timed out"),
+        ] {
+            let names: Vec<&str> = results
+                .iter()
+                .filter(|result| result.kind == kind && result.status == status)
+                .map(|result| result.name.as_str())
+                .collect();
+            if !names.is_empty() {
+                lines.push(format!("{label} {kind} checks: {}", names.join(", ")));
+            }
+        }
+    }
+    let warning = names_with(results, CheckStatus::Warning);
+    if !warning.is_empty() {
+        lines.push(format!("Warning checks: {}", warning.join(", ")));
+    }
+    let skipped = names_with(results, CheckStatus::Skipped);
+    if !skipped.is_empty() {
+        lines.push(format!("Skipped checks: {}", skipped.join(", ")));
+    }
+    for result in results {
+        if let (CheckStatus::Failed | CheckStatus::TimedOut | CheckStatus::Warning, Some(hint)) =
+            (result.status, &result.hint)
+        {
+            lines.push(format!("Hint for {}: {hint}", result.name));
+        }
+    }
+    lines.join("\n")
+}
+
+#[derive(Debug, Error)]
+/// why the health check failed
+pub enum DiagnosticsError {
+    #[error("cannot find any required.d folder")]
+    NoRequiredDir,
+    /// a required check or plugin of the kind ran too long
+    #[error("{0} health-check timed out, skipping remaining scripts")]
+    TimedOut(CheckKind, Diagnostics),
+    /// a required check or plugin of the kind failed
+    #[error("{0} health-check failed, skipping remaining scripts")]
+    Failed(CheckKind, Diagnostics),
+}
+
+impl DiagnosticsError {
+    /// results of the checks that ran before the health check failed
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        match self {
+            DiagnosticsError::NoRequiredDir => None,
+            DiagnosticsError::TimedOut(_, diagnostics)
+            | DiagnosticsError::Failed(_, diagnostics) => Some(diagnostics),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// how an entry is executed
+pub enum CheckRunner {
+    /// `.sh` file, run via bash whatever its mode
+    Script,
+    /// anything else, executed directly
+    Binary,
+}
+
+impl CheckRunner {
+    fn of(path: &Path) -> Self {
+        if path.extension().and_then(|ext| ext.to_str()) == Some("sh") {
+            CheckRunner::Script
+        } else {
+            CheckRunner::Binary
+        }
+    }
+
+    /// name used in output
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckRunner::Script => "script",
+            CheckRunner::Binary => "binary",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// a file discovered in one of greenboot's directories
+pub struct CheckEntry {
+    /// file name, as matched by DISABLED_HEALTHCHECKS
+    pub name: String,
+    pub kind: CheckKind,
+    /// install path the entry was found in
+    pub source: PathBuf,
+    pub path: PathBuf,
+    pub runner: CheckRunner,
+    /// any exec bit is set
+    pub executable: bool,
+    /// listed in DISABLED_HEALTHCHECKS
+    pub disabled: bool,
+    /// symlink to /dev/null, hiding the entry of the same name
+    pub masked: bool,
+    /// entry of an earlier install path this one replaces
+    pub overrides: Option<PathBuf>,
+    /// what the entry declares about itself
+    pub meta: CheckMeta,
+}
+
+impl CheckEntry {
+    /// binaries without an exec bit are skipped, scripts run via bash anyway
+    pub fn lacks_exec_bit(&self) -> bool {
+        !self.masked && self.runner == CheckRunner::Binary && !self.executable
+    }
+
+    /// true if greenboot runs this entry
+    pub fn will_run(&self) -> bool {
+        !self.disabled && !self.masked && !self.lacks_exec_bit()
+    }
+
+    /// true if After= or Requires= naming `dep` refer to this entry, with
+    /// or without its extension
+    pub fn answers_to(&self, dep: &str) -> bool {
+        self.name == dep
+            || Path::new(&self.name)
+                .file_stem()
+                .is_some_and(|stem| stem == dep)
+    }
+
+    /// first declared condition not met on this system, if any
+    pub fn unmet_condition(&self) -> Option<&Condition> {
+        self.meta
+            .conditions
+            .iter()
+            .find(|condition| !condition.holds())
+    }
+
+    /// path and description, as used in logs
+    fn describe(&self) -> String {
+        match &self.meta.description {
+            Some(description) => format!("{} ({description})", self.path.display()),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "kind": self.kind.as_str(),
+            "source": self.source,
+            "path": self.path,
+            "runner": self.runner.as_str(),
+            "executable": self.executable,
+            "disabled": self.disabled,
+            "masked": self.masked,
+            "overrides": self.overrides,
+            "description": self.meta.description,
+            "timeout": self.meta.timeout.map(|t| t.map_or(0, |d| d.as_secs())),
+            "after": self.meta.after,
+            "requires": self.meta.requires,
+            "conditions": self
+                .meta
+                .conditions
+                .iter()
+                .map(Condition::to_string)
+                .collect::<Vec<_>>(),
+            "skipped_no_exec": self.lacks_exec_bit(),
+            "will_run": self.will_run(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// What a check declares about itself in `# greenboot:` header lines of a
+/// script or in the `<name>.toml` sidecar of a binary, e.g.
+/// `# greenboot: Description=DNS works Timeout=30s After=01_dns.sh`
+pub struct CheckMeta {
+    pub description: Option<String>,
+    /// limit replacing the default one, none inside for no limit
+    pub timeout: Option<Option<Duration>>,
+    /// checks of the same directory that run first
+    pub after: Vec<String>,
+    /// checks that run first and must succeed, else this one fails
+    pub requires: Vec<String>,
+    /// all must hold for the check to run, else it is skipped
+    pub conditions: Vec<Condition>,
+}
+
+/// keys of `# greenboot:` header lines besides the conditions
+const META_KEYS: [&str; 4] = ["Description", "Timeout", "After", "Requires"];
+
+impl CheckMeta {
+    /// Reads the metadata of the check at `path`, invalid keys and values
+    /// are logged and ignored
+    fn load(path: &Path, runner: CheckRunner) -> Self {
+        let (meta, problems) = match runner {
+            CheckRunner::Script => match fs::read(path) {
+                Ok(script) => Self::parse_header(&String::from_utf8_lossy(&script)),
+                Err(_) => (Self::default(), vec![]),
+            },
+            CheckRunner::Binary => {
+                let mut sidecar = path.as_os_str().to_owned();
+                sidecar.push(".toml");
+                let sidecar = PathBuf::from(sidecar);
+                if !sidecar.is_file() {
+                    return Self::default();
+                }
+                Self::parse_sidecar(&sidecar)
+            }
+        };
+        for problem in problems {
+            log::warn!("ignoring metadata of {}: {problem}", path.display());
+        }
+        meta
+    }
+
+    /// Parses the `# greenboot:` lines of the leading comment block of a
+    /// script. Values run up to the next `Key=`, After= and Requires= take
+    /// space separated names and may be given more than once.
+    fn parse_header(script: &str) -> (Self, Vec<String>) {
+        let mut meta = Self::default();
+        let mut problems = vec![];
+        let lines = script
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.is_empty() || line.starts_with('#'));
+        for line in lines {
+            let Some(fields) = line
+                .trim_start_matches('#')
+                .trim_start()
+                .strip_prefix("greenboot:")
+            else {
+                continue;
+            };
+            let mut pairs: Vec<(&str, String)> = vec![];
+            for word in fields.split_whitespace() {
+                let key = word
+                    .split_once('=')
+                    .map(|(key, _)| key)
+                    .filter(|key| key.starts_with(|c: char| c.is_ascii_uppercase()))
+                    .filter(|key| key.chars().all(|c| c.is_ascii_alphabetic()));
+                match key {
+                    Some(key) => pairs.push((key, word[key.len() + 1..].to_string())),
+                    None => match pairs.last_mut() {
+                        Some((_, value)) => {
+                            value.push(' ');
+                            value.push_str(word);
+                        }
+                        None => problems.push(format!("expected Key=Value, found {word}")),
+                    },
+                }
+            }
+            for (key, value) in pairs {
+                if let Err(problem) = meta.set(key, &value) {
+                    problems.push(problem);
+                }
+            }
+        }
+        (meta, problems)
+    }
+
+    /// Parses a sidecar with the header keys in snake case, e.g. `timeout`
+    /// or `condition_path_exists`. Keys given as array set every element.
+    fn parse_sidecar(sidecar: &Path) -> (Self, Vec<String>) {
+        let config = match Config::builder()
+            .add_source(File::from(sidecar).format(FileFormat::Toml))
+            .build()
+        {
+            Ok(config) => config,
+            Err(e) => return (Self::default(), vec![e.to_string()]),
+        };
+        let mut meta = Self::default();
+        let mut problems = vec![];
+        let keys = META_KEYS
+            .into_iter()
+            .chain(ConditionKind::ALL.map(ConditionKind::key));
+        for key in keys {
+            let sidecar_key = snake_case(key);
+            let values = match config.get_array(&sidecar_key) {
+                Ok(values) => values
+                    .into_iter()
+                    .filter_map(|value| value.into_string().ok())
+                    .collect(),
+                Err(_) => match config.get_string(&sidecar_key) {
+                    Ok(value) => vec![value],
+                    Err(_) => continue,
+                },
+            };
+            for value in values {
+                if let Err(problem) = meta.set(key, &value) {
+                    problems.push(problem);
+                }
+            }
+        }
+        (meta, problems)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let names = || value.split_whitespace().map(String::from);
+        match key {
+            "Description" => self.description = Some(value.trim().to_string()),
+            "Timeout" => {
+                self.timeout =
+                    Some(parse_timeout(value).ok_or_else(|| format!("invalid Timeout={value}"))?)
+            }
+            "After" => self.after.extend(names()),
+            "Requires" => self.requires.extend(names()),
+            _ => {
+                let kind = ConditionKind::ALL
+                    .into_iter()
+                    .find(|kind| kind.key() == key)
+                    .ok_or_else(|| format!("unknown key {key}"))?;
+                let value = value.trim();
+                let (negate, value) = match value.strip_prefix('!') {
+                    Some(value) => (true, value.trim_start()),
+                    None => (false, value),
+                };
+                if value.is_empty() {
+                    return Err(format!("empty {key}="));
+                }
+                self.conditions.push(Condition {
+                    kind,
+                    negate,
+                    value: value.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `ConditionPathExists` as `condition_path_exists`, the sidecar spelling
+fn snake_case(key: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in key.char_indices() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// what a check condition tests, modeled on systemd's Condition= directives
+pub enum ConditionKind {
+    /// the path exists below the sysroot
+    PathExists,
+    /// `systemd-detect-virt` reports the technology, `vm`, `container`,
+    /// or any (`yes`) or none (`no`) at all
+    Virtualization,
+    /// the CPU architecture, as Rust (`x86_64`) or systemd (`x86-64`) names it
+    Architecture,
+    /// the kernel command line holds the word, or an assignment to it
+    KernelCommandLine,
+    /// the system is deployed by `bootc` or `rpm-ostree`
+    Deployment,
+}
+
+impl ConditionKind {
+    pub const ALL: [ConditionKind; 5] = [
+        ConditionKind::PathExists,
+        ConditionKind::Virtualization,
+        ConditionKind::Architecture,
+        ConditionKind::KernelCommandLine,
+        ConditionKind::Deployment,
+    ];
+
+    /// key in `# greenboot:` header lines
+    pub fn key(self) -> &'static str {
+        match self {
+            ConditionKind::PathExists => "ConditionPathExists",
+            ConditionKind::Virtualization => "ConditionVirtualization",
+            ConditionKind::Architecture => "ConditionArchitecture",
+            ConditionKind::KernelCommandLine => "ConditionKernelCommandLine",
+            ConditionKind::Deployment => "ConditionDeployment",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// a condition a check declares, e.g. `ConditionPathExists=/dev/watchdog`
+pub struct Condition {
+    pub kind: ConditionKind,
+    /// declared with a leading `!`, the check runs if the test fails
+    pub negate: bool,
+    pub value: String,
+}
+
+impl Condition {
+    /// true if the condition is met on the running system
+    pub fn holds(&self) -> bool {
+        let met = match self.kind {
+            ConditionKind::PathExists => rooted(&self.value).exists(),
+            ConditionKind::Virtualization => {
+                let (virt, container) = detected_virtualization();
+                virtualization_is(virt, *container, &self.value)
+            }
+            ConditionKind::Architecture => architecture_is(
+                std::env::consts::ARCH,
+                cfg!(target_endian = "big"),
+                &self.value,
+            ),
+            // the running kernel's, even below a sysroot
+            ConditionKind::KernelCommandLine => fs::read_to_string("/proc/cmdline")
+                .is_ok_and(|cmdline| cmdline_has(&cmdline, &self.value)),
+            ConditionKind::Deployment => {
+                static DEPLOYMENT: OnceLock<Option<&str>> = OnceLock::new();
+                *DEPLOYMENT.get_or_init(detect_os_deployment) == Some(self.value.as_str())
+            }
+        };
+        met != self.negate
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let negate = if self.negate { "!" } else { "" };
+        write!(f, "{}={negate}{}", self.kind.key(), self.value)
+    }
+}
+
+/// technology reported by `systemd-detect-virt`, `none` if there is none,
+/// and whether it is a container
+fn detected_virtualization() -> &'static (String, bool) {
+    static VIRTUALIZATION: OnceLock<(String, bool)> = OnceLock::new();
+    VIRTUALIZATION.get_or_init(|| {
+        let virt = Command::new("systemd-detect-virt")
+            .stderr(Stdio::null())
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .ok()
+            .filter(|virt| !virt.is_empty())
+            .unwrap_or_else(|| String::from("none"));
+        let container = Command::new("systemd-detect-virt")
+            .arg("--container")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        (virt, container)
+    })
+}
+
+fn virtualization_is(virt: &str, container: bool, value: &str) -> bool {
+    let virtualized = virt != "none";
+    match value {
+        "yes" | "true" | "1" => virtualized,
+        "no" | "false" | "0" => !virtualized,
+        "vm" => virtualized && !container,
+        "container" => container,
+        technology => virt == technology,
+    }
+}
+
+/// systemd's name of the architecture Rust calls `arch`, which tells the
+/// byte orders apart
+fn systemd_architecture(arch: &str, big_endian: bool) -> Option<&'static str> {
+    let name = match (arch, big_endian) {
+        ("x86_64", _) => "x86-64",
+        ("x86", _) => "x86",
+        ("aarch64", false) => "arm64",
+        ("aarch64", true) => "arm64-be",
+        ("arm", false) => "arm",
+        ("arm", true) => "arm-be",
+        ("powerpc64", false) => "ppc64-le",
+        ("powerpc64", true) => "ppc64",
+        ("powerpc", false) => "ppc-le",
+        ("powerpc", true) => "ppc",
+        ("mips64", false) => "mips64-le",
+        ("mips64", true) => "mips64",
+        ("mips", false) => "mips-le",
+        ("mips", true) => "mips",
+        ("s390x", _) => "s390x",
+        ("riscv64", _) => "riscv64",
+        ("riscv32", _) => "riscv32",
+        ("loongarch64", _) => "loongarch64",
+        ("sparc64", _) => "sparc64",
+        ("m68k", _) => "m68k",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// true if `value` is systemd's name for the architecture, or Rust's
+fn architecture_is(arch: &str, big_endian: bool, value: &str) -> bool {
+    systemd_architecture(arch, big_endian) == Some(value) || arch == value
+}
+
+/// true if `cmdline` holds `word`, or an assignment to it if `word` has no `=`
+fn cmdline_has(cmdline: &str, word: &str) -> bool {
+    cmdline.split_whitespace().any(|token| {
+        token == word
+            || (!word.contains('=') && token.split_once('=').is_some_and(|(name, _)| name == word))
+    })
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// everything greenboot would run, see [`list_checks`]
+pub struct CheckInventory {
+    pub entries: Vec<CheckEntry>,
+    /// DISABLED_HEALTHCHECKS names that match no required or wanted check
+    pub unmatched_disabled: Vec<String>,
+    /// unknown dependencies and dependency cycles of the checks
+    pub problems: Vec<String>,
+}
+
+impl CheckInventory {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "checks": self.entries.iter().map(CheckEntry::to_json).collect::<Vec<_>>(),
+            "unmatched_disabled": self.unmatched_disabled,
+            "problems": self.problems,
+        })
+    }
+}
+
+/// lists all required, wanted, green and red entries in run order
+pub fn list_checks(disabled: &[String]) -> CheckInventory {
+    list_checks_in(&install_paths(), disabled)
+}
+
+pub(crate) fn list_checks_in(
+    install_paths: &[impl AsRef<Path>],
+    disabled: &[String],
+) -> CheckInventory {
+    let mut inventory = CheckInventory::default();
+    for kind in CheckKind::ALL {
+        let earlier = earlier_entries(kind, &inventory.entries);
+        let (entries, problems) =
+            order_entries(kind_entries_in(install_paths, kind, disabled), &earlier);
+        inventory.entries.extend(entries);
+        inventory.problems.extend(problems);
+    }
+
+    let mut seen = HashSet::new();
+    inventory.unmatched_disabled = disabled
+        .iter()
+        .filter(|name| {
+            !inventory
+                .entries
+                .iter()
+                .any(|entry| entry.kind.is_check() && &entry.name == *name)
+        })
+        .filter(|name| seen.insert(name.as_str()))
+        .cloned()
+        .collect();
+    inventory
+}
+
+/// The `kind` entries of all install paths merged by file name in lexical
+/// order, like systemd unit directories: an entry replaces the one of the
+/// same name in an earlier install path, a symlink to /dev/null masks it.
+fn kind_entries_in(
+    install_paths: &[impl AsRef<Path>],
+    kind: CheckKind,
+    disabled: &[String],
+) -> Vec<CheckEntry> {
+    let mut merged: BTreeMap<String, CheckEntry> = BTreeMap::new();
+    for install_path in install_paths {
+        let install_path = install_path.as_ref();
+        for path in dir_entries(&install_path.join(kind.dir())) {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            // sidecars hold the metadata of binaries and never run
+            if name.ends_with(".toml") {
+                continue;
+            }
+            let masked = is_masked(&path);
+            let executable = if masked {
+                false
+            } else {
+                match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => {
+                        metadata.permissions().mode() & 0o111 != 0
+                    }
+                    _ => continue,
+                }
+            };
+            let overrides = merged.remove(name).map(|entry| entry.path);
+            let runner = CheckRunner::of(&path);
+            merged.insert(
+                name.to_string(),
+                CheckEntry {
+                    name: name.to_string(),
+                    kind,
+                    source: install_path.to_path_buf(),
+                    runner,
+                    executable,
+                    disabled: kind.is_check() && disabled.iter().any(|d| d == name),
+                    masked,
+                    overrides,
+                    meta: if masked {
+                        CheckMeta::default()
+                    } else {
+                        CheckMeta::load(&path, runner)
+                    },
+                    path,
+                },
+            );
+        }
+    }
+    merged.into_values().collect()
+}
+
+/// checks run before those of `kind`, whose dependencies they may satisfy:
+/// required ones for plugins, both for wanted checks
+fn earlier_entries(kind: CheckKind, entries: &[CheckEntry]) -> Vec<CheckEntry> {
+    let earlier: &[CheckKind] = match kind {
+        CheckKind::Plugin => &[CheckKind::Required],
+        CheckKind::Wanted => &[CheckKind::Required, CheckKind::Plugin],
+        _ => &[],
+    };
+    entries
+        .iter()
+        .filter(|entry| earlier.contains(&entry.kind))
+        .cloned()
+        .collect()
+}
+
+/// Orders `entries` of one directory, given in name order, so every check
+/// runs after the ones its After= and Requires= name and in name order
+/// otherwise. Dependencies on `earlier` entries, run before the directory,
+/// hold already. Returns the unknown dependencies and cycles found, checks
+/// in a cycle keep their name order.
+fn order_entries(
+    entries: Vec<CheckEntry>,
+    earlier: &[CheckEntry],
+) -> (Vec<CheckEntry>, Vec<String>) {
+    let mut problems = vec![];
+    let mut waits_for: Vec<Vec<usize>> = vec![];
+    for entry in &entries {
+        let mut waits = vec![];
+        for dep in entry.meta.after.iter().chain(&entry.meta.requires) {
+            match entries.iter().position(|other| other.answers_to(dep)) {
+                Some(index) => waits.push(index),
+                None if earlier.iter().any(|other| other.answers_to(dep)) => {}
+                None => problems.push(format!(
+                    "{} check {} depends on unknown check {dep}",
+                    entry.kind, entry.name
+                )),
+            }
+        }
+        waits_for.push(waits);
+    }
+
+    let mut placed = vec![false; entries.len()];
+    let mut order = vec![];
+    while let Some(next) =
+        (0..entries.len()).find(|&i| !placed[i] && waits_for[i].iter().all(|&dep| placed[dep]))
+    {
+        placed[next] = true;
+        order.push(next);
+    }
+    let stuck: Vec<usize> = (0..entries.len()).filter(|&i| !placed[i]).collect();
+    if !stuck.is_empty() {
+        problems.push(format!(
+            "{} checks {} cannot be ordered, their dependencies form a cycle",
+            entries[stuck[0]].kind,
+            stuck
+                .iter()
+                .map(|&i| entries[i].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        order.extend(stuck);
+    }
+
+    let mut entries: Vec<Option<CheckEntry>> = entries.into_iter().map(Some).collect();
+    let ordered = order
+        .into_iter()
+        .map(|i| entries[i].take().expect("every entry is ordered once"))
+        .collect();
+    (ordered, problems)
+}
+
+/// logs the problems found by [`order_entries`]
+fn report_problems(problems: &[String]) {
+    for problem in problems {
+        log::error!("check configuration error: {problem}");
+    }
+}
+
+/// true if `path` is a symlink to /dev/null
+fn is_masked(path: &Path) -> bool {
+    fs::read_link(path).is_ok_and(|target| target == Path::new("/dev/null"))
+}
+
+/// sorted entries of `dir`, empty if it cannot be read
+fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+    let pattern = glob::Pattern::escape(&dir.to_string_lossy());
+    match glob(&format!("{pattern}/*")) {
+        Ok(paths) => paths.filter_map(Result::ok).collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// command running `entry` the way greenboot does: `.sh` via bash, anything else directly
+fn check_command(entry: &Path) -> Command {
+    match CheckRunner::of(entry) {
+        CheckRunner::Script => {
+            let mut command = Command::new("bash");
+            command.arg("-C").arg(entry);
+            command
+        }
+        CheckRunner::Binary => Command::new(entry),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// how long checks and hooks may run before their process group is killed
+pub struct CheckTimeouts {
+    /// limit for entries without an override, none for no limit
+    pub default: Option<Duration>,
+    /// limits by file name, none for no limit
+    pub overrides: HashMap<String, Option<Duration>>,
+}
+
+impl Default for CheckTimeouts {
+    fn default() -> Self {
+        Self {
+            default: Some(DEFAULT_CHECK_TIMEOUT),
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl CheckTimeouts {
+    /// limit for `entry`, configured overrides win over its metadata
+    pub fn for_entry(&self, entry: &CheckEntry) -> Option<Duration> {
+        self.overrides
+            .get(&entry.name)
+            .copied()
+            .or(entry.meta.timeout)
+            .unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// how greenboot runs the checks and hooks of a directory
+pub struct CheckPolicy {
+    pub timeouts: CheckTimeouts,
+    /// most checks running at once, 1 runs them one after another
+    pub parallelism: usize,
+    /// required checks and plugins that may run alongside each other
+    pub independent: Vec<String>,
+    /// checks run again when they fail, by file name
+    pub retries: HashMap<String, RetryPolicy>,
+    /// what a plugin reporting UNKNOWN counts as
+    pub plugin_unknown: CheckStatus,
+}
+
+impl Default for CheckPolicy {
+    fn default() -> Self {
+        Self {
+            timeouts: CheckTimeouts::default(),
+            parallelism: DEFAULT_CHECK_PARALLELISM,
+            independent: vec![],
+            retries: HashMap::new(),
+            plugin_unknown: CheckStatus::Warning,
+        }
+    }
+}
+
+impl CheckPolicy {
+    /// Effective settings, named like the GREENBOOT_CHECK_* options they
+    /// come from. Timeouts and delays are in seconds, 0 meaning no limit.
+    pub fn to_json(&self) -> Value {
+        let secs = |timeout: Option<Duration>| timeout.map_or(0, |d| d.as_secs());
+        json!({
+            "check_timeout": secs(self.timeouts.default),
+            "check_timeouts": self
+                .timeouts
+                .overrides
+                .iter()
+                .map(|(name, timeout)| (name.clone(), json!(secs(*timeout))))
+                .collect::<serde_json::Map<_, _>>(),
+            "check_parallelism": self.parallelism,
+            "independent_checks": self.independent,
+            "check_retries": self
+                .retries
+                .iter()
+                .map(|(name, retry)| {
+                    let retry = json!({
+                        "attempts": retry.attempts,
+                        "delay": retry.delay.as_secs(),
+                        "backoff": retry.backoff,
+                    });
+                    (name.clone(), retry)
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "plugin_unknown": self.plugin_unknown.as_str(),
+        })
+    }
+
+    /// retries of the check called `name`, a single attempt if none are set
+    pub fn retry_for(&self, name: &str) -> RetryPolicy {
+        self.retries.get(name).copied().unwrap_or_default()
+    }
+
+    /// Groups the `kind` entries, in run order, into batches that run
+    /// concurrently. Wanted checks share a batch, required checks and
+    /// plugins only with neighbouring independent ones and hooks run alone.
+    /// A check never shares a batch with one it depends on.
+    fn batches(&self, kind: CheckKind, entries: Vec<CheckEntry>) -> Vec<Vec<CheckEntry>> {
+        let mut batches: Vec<Vec<CheckEntry>> = vec![];
+        let mut shared = false;
+        for entry in entries {
+            let joins = self.parallelism > 1
+                && match kind {
+                    CheckKind::Wanted => true,
+                    CheckKind::Required | CheckKind::Plugin => {
+                        self.independent.contains(&entry.name)
+                    }
+                    _ => false,
+                };
+            match batches.last_mut() {
+                Some(batch) if joins && shared && !depends_on_any(&entry, batch) => {
+                    batch.push(entry)
+                }
+                _ => batches.push(vec![entry]),
+            }
+            shared = joins;
+        }
+        batches
+    }
+}
+
+/// true if `entry` names one of `others` in After= or Requires=
+fn depends_on_any(entry: &CheckEntry, others: &[CheckEntry]) -> bool {
+    entry
+        .meta
+        .after
+        .iter()
+        .chain(&entry.meta.requires)
+        .any(|dep| others.iter().any(|other| other.answers_to(dep)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// how often a failing check runs before it counts as failed
+pub struct RetryPolicy {
+    /// runs in total, 1 means no retries
+    pub attempts: u32,
+    /// wait before the first retry
+    pub delay: Duration,
+    /// factor the wait grows by with every further retry
+    pub backoff: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            delay: Duration::from_secs(5),
+            backoff: 1,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// wait after the failed `attempt`, counting from 1
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        self.delay
+            .saturating_mul(self.backoff.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// Parses a `name:attempts[:delay[:backoff]]` entry of GREENBOOT_CHECK_RETRIES,
+/// with the delay in seconds
+pub fn parse_retry_override(entry: &str) -> Option<(String, RetryPolicy)> {
+    let mut fields = entry.split(':');
+    let name = fields.next().filter(|name| !name.is_empty())?;
+    let mut retry = RetryPolicy {
+        attempts: fields.next()?.trim().parse().ok().filter(|&n| n > 0)?,
+        ..RetryPolicy::default()
+    };
+    if let Some(delay) = fields.next() {
+        retry.delay = Duration::from_secs(delay.trim().parse().ok()?);
+    }
+    if let Some(backoff) = fields.next() {
+        retry.backoff = backoff.trim().parse().ok().filter(|&n| n > 0)?;
+    }
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((name.to_string(), retry))
+}
+
+/// Parses a timeout like `30`, `30s`, `5m` or `1h`, `0` and `infinity`
+/// meaning no limit. Used for the configuration and the Timeout= metadata alike.
+pub fn parse_timeout(raw: &str) -> Option<Option<Duration>> {
+    let raw = raw.trim();
+    if raw == "infinity" {
+        return Some(None);
+    }
+    let (number, scale) = match raw.char_indices().last()? {
+        (i, 's') => (&raw[..i], 1),
+        (i, 'm') => (&raw[..i], 60),
+        (i, 'h') => (&raw[..i], 3600),
+        _ => (raw, 1),
+    };
+    let secs = number.parse::<u64>().ok()?.checked_mul(scale)?;
+    Some((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
+/// Parses a `name:timeout` entry of GREENBOOT_CHECK_TIMEOUTS
+pub fn parse_timeout_override(entry: &str) -> Option<(String, Option<Duration>)> {
+    let (name, timeout) = entry.rsplit_once(':')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), parse_timeout(timeout)?))
+}
+
+#[derive(Debug, Error)]
+/// why a check or hook run by greenboot did not succeed
+pub enum CheckError {
+    #[error("{kind} script {path} failed!{}\n{stdout}\n{stderr}", attempts_note(*attempts))]
+    Failed {
+        kind: String,
+        path: String,
+        stdout: String,
+        stderr: String,
+        /// runs including retries
+        attempts: u32,
+        /// what the last run wrote to its result channel
+        report: CheckReport,
+    },
+    #[error(
+        "{kind} script {path} timed out after {} and was killed{}",
+        format_duration(*timeout),
+        attempts_note(*attempts)
+    )]
+    TimedOut {
+        kind: String,
+        path: String,
+        timeout: Duration,
+        /// runs including retries
+        attempts: u32,
+        /// what the last run wrote to its result channel before it was killed
+        report: CheckReport,
+    },
+    #[error("{kind} script {path} was not run, {dependency} it requires did not succeed")]
+    DependencyFailed {
+        kind: String,
+        path: String,
+        dependency: String,
+    },
+}
+
+impl CheckError {
+    /// runs of the check including retries
+    pub fn attempts(&self) -> u32 {
+        match self {
+            CheckError::Failed { attempts, .. } | CheckError::TimedOut { attempts, .. } => {
+                *attempts
+            }
+            CheckError::DependencyFailed { .. } => 0,
+        }
+    }
+
+    /// what the check wrote to its result channel, none if it did not run
+    pub fn report(&self) -> Option<&CheckReport> {
+        match self {
+            CheckError::Failed { report, .. } | CheckError::TimedOut { report, .. } => Some(report),
+            CheckError::DependencyFailed { .. } => None,
+        }
+    }
+}
+
+/// `duration` for messages, e.g. `300s` or `0.5s`
+pub fn format_duration(duration: Duration) -> String {
+    format!("{}s", duration.as_secs_f64())
+}
+
+/// mentions the attempts in error messages of retried checks only
+fn attempts_note(attempts: u32) -> String {
+    if attempts > 1 {
+        format!(" ({attempts} attempts)")
+    } else {
+        String::new()
+    }
+}
+
+/// output of a process run by [`run_with_timeout`]
+struct TimedOutput {
+    /// exit status, or the timeout the process was killed after
+    status: Result<ExitStatus, Duration>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/// how long piped output is still collected once a process exited or was
+/// killed, for descendants that left its process group and keep the pipes open
+static PIPE_GRACE: Duration = Duration::from_secs(2);
+
+/// output read from a pipe so far, and whether the pipe was closed
+type PipeReader = (Arc<Mutex<Vec<u8>>>, mpsc::Receiver<()>);
+
+/// Hands the terminal greenboot runs in to a process group until dropped, so
+/// Ctrl-C reaches the group and it may read the terminal
+struct Foreground {
+    /// greenboot's own process group, which gets the terminal back
+    owner: Pid,
+}
+
+impl Foreground {
+    /// none if greenboot does not own a terminal to hand over
+    fn give(group: Pid) -> Option<Self> {
+        let owner = getpgrp();
+        if tcgetpgrp(io::stdin()).ok()? != owner {
+            return None;
+        }
+        if let Err(e) = tcsetpgrp(io::stdin(), group) {
+            log::warn!("cannot hand the terminal to process group {group}: {e}");
+            return None;
+        }
+        // it may have been stopped for reading the terminal before it owned it
+        let _ = killpg(group, Signal::SIGCONT);
+        Some(Self { owner })
+    }
+}
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        // taking the terminal back from the background raises SIGTTOU
+        let mut ttou = SigSet::empty();
+        ttou.add(Signal::SIGTTOU);
+        let previous = ttou.thread_swap_mask(SigmaskHow::SIG_BLOCK);
+        if let Err(e) = tcsetpgrp(io::stdin(), self.owner) {
+            log::warn!("cannot take the terminal back: {e}");
+        }
+        if let Ok(previous) = previous {
+            let _ = previous.thread_set_mask();
+        }
+    }
+}
+
+/// Runs `command` in a process group of its own. If it runs longer than
+/// `timeout` the whole group is killed, so children of hung scripts do not
+/// linger either. With `foreground` the group gets greenboot's terminal
+/// while it runs. Piped output is collected while the process runs, and for
+/// at most [`PIPE_GRACE`] after it ended.
+fn run_with_timeout(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    foreground: bool,
+) -> io::Result<TimedOutput> {
+    let mut child = command.process_group(0).spawn()?;
+    let pid = Pid::from_raw(child.id() as i32);
+    let terminal = if foreground {
+        Foreground::give(pid)
+    } else {
+        None
+    };
+    let collect = |pipe: Option<Box<dyn Read + Send>>| -> PipeReader {
+        let buf = Arc::new(Mutex::new(vec![]));
+        let (closed, receiver) = mpsc::channel();
+        if let Some(mut pipe) = pipe {
+            let buf = Arc::clone(&buf);
+            thread::spawn(move || {
+                let mut chunk = [0; 8192];
+                loop {
+                    match pipe.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(n) => buf.lock().unwrap().extend_from_slice(&chunk[..n]),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(_) => break,
+                    }
+                }
+                let _ = closed.send(());
+            });
+        }
+        (buf, receiver)
+    };
+    let stdout = collect(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = collect(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(child.wait()));
+    let status = match timeout {
+        Some(timeout) => match receiver.recv_timeout(timeout) {
+            Ok(status) => Ok(status?),
+            Err(_) => {
+                if let Err(e) = killpg(pid, Signal::SIGKILL) {
+                    log::warn!("cannot kill process group {pid}: {e}");
+                }
+                receiver.recv().map_err(io::Error::other)??;
+                Err(timeout)
+            }
+        },
+        None => Ok(receiver.recv().map_err(io::Error::other)??),
+    };
+    drop(terminal);
+
+    // a reader still blocked after the grace period is left behind, it ends
+    // once the last descendant holding the pipe exits
+    let deadline = Instant::now() + PIPE_GRACE;
+    let program = command.get_program().to_string_lossy().into_owned();
+    let drain = |(buf, closed): PipeReader| {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if let Err(mpsc::RecvTimeoutError::Timeout) = closed.recv_timeout(left) {
+            log::warn!("descendants of {program} keep its output open, ignoring the rest of it");
+        }
+        std::mem::take(&mut *buf.lock().unwrap())
+    };
+    Ok(TimedOutput {
+        status,
+        stdout: drain(stdout),
+        stderr: drain(stderr),
+    })
+}
+
+/// finds the required and wanted checks called `name`, in run order
+pub fn find_checks(name: &str) -> Vec<CheckEntry> {
+    list_checks(&[])
+        .entries
+        .into_iter()
+        .filter(|entry| entry.kind.is_check() && entry.name == name)
+        .collect()
+}
+
+/// Runs a single check with its output going to greenboot's stdout/stderr,
+/// returns whether it passed or was skipped.
+///
+/// Only the check itself is executed, the boot state, motd and hooks are
+/// left alone.
+pub fn run_check(entry: &CheckEntry, policy: &CheckPolicy) -> Result<CheckStatus> {
+    if entry.masked {
+        bail!(
+            "{} check {} is masked by {}",
+            entry.kind,
+            entry.name,
+            entry.path.display()
+        );
+    }
+    if entry.lacks_exec_bit() {
+        bail!(
+            "{} check {} lacks the exec bit and is skipped by greenboot",
+            entry.kind,
+            entry.path.display()
+        );
+    }
+    if let Some(condition) = entry.unmet_condition() {
+        log::info!(
+            "{} check {} skipped (condition): {condition} not met",
+            entry.kind,
+            entry.path.display()
+        );
+        return Ok(CheckStatus::Skipped);
+    }
+    log::info!("running {} check {}", entry.kind, entry.path.display());
+    let timeout = policy.timeouts.for_entry(entry);
+    let channel = tempfile::NamedTempFile::new().context("cannot create result channel")?;
+    // in the foreground, so Ctrl-C and the terminal reach the check
+    let output = run_with_timeout(
+        check_command(&entry.path).env(RESULT_FILE_ENV, channel.path()),
+        timeout,
+        true,
+    )
+    .with_context(|| format!("cannot run {}", entry.path.display()))?;
+    let report = CheckReport::read(channel.path(), &entry.path);
+    if let Some(message) = &report.message {
+        log::info!("{} check {}: {message}", entry.kind, entry.path.display());
+    }
+    if let Some(hint) = &report.hint {
+        log::info!("{} check {} hint: {hint}", entry.kind, entry.path.display());
+    }
+    let exit = match output.status {
+        Ok(exit) => exit,
+        Err(timeout) => {
+            return Err(CheckError::TimedOut {
+                kind: entry.kind.to_string(),
+                path: entry.path.to_string_lossy().into_owned(),
+                timeout,
+                attempts: 1,
+                report,
+            }
+            .into());
+        }
+    };
+    let status = finished_status(entry.kind, exit.code(), policy.plugin_unknown, &report);
+    let path = entry.path.display();
+    match status {
+        CheckStatus::Failed => bail!("{} check {path} failed: {exit}", entry.kind),
+        CheckStatus::Skipped => log::info!("{} check {path} skipped ({exit})", entry.kind),
+        CheckStatus::Warning => log::warn!("{} check {path} warning ({exit})", entry.kind),
+        _ => log::info!("{} check {path} success!", entry.kind),
+    }
+    Ok(status)
+}
+
 /// run required.d and wanted.d scripts.
 /// If a required script fails, log the error, and skip remaining checks.
-pub fn run_diagnostics(skipped: Vec<String>) -> Result<Vec<String>> {
+pub fn run_diagnostics(
+    skipped: Vec<String>,
+    policy: &CheckPolicy,
+) -> Result<Diagnostics, DiagnosticsError> {
+    run_diagnostics_in(&install_paths(), skipped, policy)
+}
+
+fn run_diagnostics_in(
+    install_paths: &[impl AsRef<Path>],
+    skipped: Vec<String>,
+    policy: &CheckPolicy,
+) -> Result<Diagnostics, DiagnosticsError> {
+    let mut diagnostics = Diagnostics::default();
     let mut path_exists = false;
     let mut all_skipped = HashSet::new();
 
@@ -22,170 +1725,457 @@ pub fn run_diagnostics(skipped: Vec<String>) -> Result<Vec<String>> {
     let disabled_scripts: HashSet<String> = skipped.clone().into_iter().collect();
 
     // Run required checks
-    for path in GREENBOOT_INSTALL_PATHS {
-        let greenboot_required_path = format!("{path}/check/required.d/");
-        if !Path::new(&greenboot_required_path).is_dir() {
-            log::warn!("skipping test as {greenboot_required_path} is not a dir");
+    for path in install_paths {
+        let greenboot_required_path = path.as_ref().join(CheckKind::Required.dir());
+        if !greenboot_required_path.is_dir() {
+            log::warn!(
+                "skipping test as {} is not a dir",
+                greenboot_required_path.display()
+            );
             continue;
         }
         path_exists = true;
-        let result = run_scripts("required", &greenboot_required_path, Some(&skipped));
-        all_skipped.extend(result.skipped);
-
-        if !result.errors.is_empty() {
-            log::error!("required script error:");
-            result.errors.iter().for_each(|e| log::error!("{e}"));
-            bail!("required health-check failed, skipping remaining scripts");
-        }
     }
 
     if !path_exists {
-        bail!("cannot find any required.d folder");
+        return Err(DiagnosticsError::NoRequiredDir);
     }
 
-    // Run wanted checks
-    for path in GREENBOOT_INSTALL_PATHS {
-        let greenboot_wanted_path = format!("{path}/check/wanted.d/");
-        let result = run_scripts("wanted", &greenboot_wanted_path, Some(&skipped));
-        all_skipped.extend(result.skipped);
+    let (required, problems) = order_entries(
+        kind_entries_in(install_paths, CheckKind::Required, &skipped),
+        &[],
+    );
+    report_problems(&problems);
+    let mut earlier = required.clone();
+    for kind in [CheckKind::Required, CheckKind::Plugin] {
+        let entries = match kind {
+            CheckKind::Required => required.clone(),
+            _ => {
+                let (plugins, problems) =
+                    order_entries(kind_entries_in(install_paths, kind, &skipped), &earlier);
+                report_problems(&problems);
+                earlier.extend(plugins.iter().cloned());
+                plugins
+            }
+        };
+        let result = run_scripts(kind, entries, policy);
+        all_skipped.extend(result.disabled);
+        diagnostics.results.extend(result.results);
 
         if !result.errors.is_empty() {
-            log::warn!("wanted script runner error:");
+            log::error!("{kind} script error:");
             result.errors.iter().for_each(|e| log::error!("{e}"));
+            let timed_out = result.errors.iter().any(|e| {
+                matches!(
+                    e.downcast_ref::<CheckError>(),
+                    Some(CheckError::TimedOut { .. })
+                )
+            });
+            log_summary(&diagnostics);
+            if timed_out {
+                return Err(DiagnosticsError::TimedOut(kind, diagnostics));
+            }
+            return Err(DiagnosticsError::Failed(kind, diagnostics));
         }
     }
 
+    // Run wanted checks
+    let (wanted, problems) = order_entries(
+        kind_entries_in(install_paths, CheckKind::Wanted, &skipped),
+        &earlier,
+    );
+    report_problems(&problems);
+    let result = run_scripts(CheckKind::Wanted, wanted, policy);
+    all_skipped.extend(result.disabled);
+    diagnostics.results.extend(result.results);
+
+    if !result.errors.is_empty() {
+        log::warn!("wanted script runner error:");
+        result.errors.iter().for_each(|e| log::error!("{e}"));
+    }
+
     // Check for disabled scripts that weren't found
-    let missing_disabled: Vec<String> = disabled_scripts
+    diagnostics.missing_disabled = disabled_scripts
         .difference(&all_skipped)
         .map(|s| s.to_string()) // Convert &String to String
         .collect();
 
-    if !missing_disabled.is_empty() {
+    if !diagnostics.missing_disabled.is_empty() {
         log::warn!(
-            "The following disabled scripts were not found in any directory: {missing_disabled:?}"
+            "The following disabled scripts were not found in any directory: {:?}",
+            diagnostics.missing_disabled
         );
     }
 
-    Ok(missing_disabled)
+    log_summary(&diagnostics);
+    Ok(diagnostics)
 }
 
-// runs all the scripts in red.d when health-check fails
-pub fn run_red() -> Vec<Box<dyn Error>> {
-    let mut errors = Vec::new();
-
-    for path in GREENBOOT_INSTALL_PATHS {
-        let red_path = format!("{path}/red.d/");
-        let result = run_scripts("red", &red_path, None); // Pass None for disabled scripts
-        errors.extend(result.errors);
+/// logs how many checks passed, failed or were skipped and which
+fn log_summary(diagnostics: &Diagnostics) {
+    let count = |status| diagnostics.names(status).len();
+    log::info!(
+        "{} checks passed, {} failed, {} timed out, {} with warnings, {} skipped, {} disabled",
+        count(CheckStatus::Passed),
+        count(CheckStatus::Failed),
+        count(CheckStatus::TimedOut),
+        count(CheckStatus::Warning),
+        count(CheckStatus::Skipped),
+        count(CheckStatus::Disabled)
+    );
+    for line in diagnostics.summary().lines() {
+        log::info!("{line}");
     }
+}
 
-    errors
+// runs all the scripts in red.d when health-check fails
+pub fn run_red(policy: &CheckPolicy) -> Vec<Box<dyn Error>> {
+    let (red, problems) =
+        order_entries(kind_entries_in(&install_paths(), CheckKind::Red, &[]), &[]);
+    report_problems(&problems);
+    run_scripts(CheckKind::Red, red, policy).errors
 }
 
 /// runs all the scripts green.d when health-check passes
-pub fn run_green() -> Vec<Box<dyn Error>> {
-    let mut errors = Vec::new();
-
-    for path in GREENBOOT_INSTALL_PATHS {
-        let green_path = format!("{path}/green.d/");
-        let result = run_scripts("green", &green_path, None); // Pass None for disabled scripts
-        errors.extend(result.errors);
-    }
-
-    errors
+pub fn run_green(policy: &CheckPolicy) -> Vec<Box<dyn Error>> {
+    let (green, problems) = order_entries(
+        kind_entries_in(&install_paths(), CheckKind::Green, &[]),
+        &[],
+    );
+    report_problems(&problems);
+    run_scripts(CheckKind::Green, green, policy).errors
 }
 
 struct ScriptRunResult {
     errors: Vec<Box<dyn Error>>,
-    skipped: Vec<String>,
+    /// names of the disabled checks
+    disabled: Vec<String>,
+    results: Vec<CheckResult>,
 }
 
-fn run_scripts(name: &str, path: &str, disabled_scripts: Option<&[String]>) -> ScriptRunResult {
-    let mut result = ScriptRunResult {
-        errors: Vec::new(),
-        skipped: Vec::new(),
+/// how a run of a check or hook ended that did not fail
+struct Outcome {
+    status: CheckStatus,
+    /// why it was skipped, the status line of plugins
+    message: Option<String>,
+    perfdata: Vec<PerfData>,
+    report: CheckReport,
+}
+
+/// Runs a single check or hook with its output captured. A failing check is
+/// run again as its retry policy allows, returns how it ended and the number
+/// of runs.
+fn run_script(
+    kind: CheckKind,
+    entry: &CheckEntry,
+    policy: &CheckPolicy,
+) -> Result<(Outcome, u32), Box<dyn Error + Send + Sync>> {
+    let retry = if kind.is_check() {
+        policy.retry_for(&entry.name)
+    } else {
+        RetryPolicy::default()
     };
+    let timeout = policy.timeouts.for_entry(entry);
+    let mut attempt = 1;
+    loop {
+        match run_attempt(kind, &entry.path, timeout, policy.plugin_unknown, attempt) {
+            Ok(outcome) => return Ok((outcome, attempt)),
+            Err(e) if attempt < retry.attempts => {
+                let delay = retry.delay_after(attempt);
+                log::warn!(
+                    "{e}\nattempt {attempt} of {} failed, retrying in {}",
+                    retry.attempts,
+                    format_duration(delay)
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
 
-    let entries = match glob(&format!("{path}*")) {
-        Ok(e) => {
-            let valid: Vec<_> = e
-                .filter_map(Result::ok)
-                .filter(|entry| {
-                    if let Ok(metadata) = fs::metadata(entry) {
-                        let mode = metadata.permissions().mode();
-                        metadata.is_file()
-                            && (entry.extension().and_then(|ext| ext.to_str()) == Some("sh")
-                                || (mode & 0o001 != 0 || mode & 0o010 != 0 || mode & 0o100 != 0))
-                    } else {
-                        false
-                    }
-                })
-                .collect();
-            Some(valid).into_iter()
+/// runs `entry` once, `attempt` is only used to report failures
+fn run_attempt(
+    kind: CheckKind,
+    entry: &Path,
+    timeout: Option<Duration>,
+    plugin_unknown: CheckStatus,
+    attempt: u32,
+) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+    let channel = tempfile::NamedTempFile::new()?;
+    // Sort between scripts and binaries since they require different commands to execute properly.
+    let output = run_with_timeout(
+        check_command(entry)
+            .env(RESULT_FILE_ENV, channel.path())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+        timeout,
+        false,
+    )?;
+    let report = CheckReport::read(channel.path(), entry);
+    // a check may also be skipped through its result channel
+    let exited_skipped = matches!(&output.status, Ok(exit) if exit.code() == Some(SKIP_EXIT_CODE));
+    let status = output
+        .status
+        .map(|exit| finished_status(kind, exit.code(), plugin_unknown, &report));
+    match status {
+        Ok(CheckStatus::Failed) => Err(Box::new(CheckError::Failed {
+            kind: kind.to_string(),
+            path: entry.to_string_lossy().into_owned(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            attempts: attempt,
+            report,
+        })),
+        Ok(status) if kind == CheckKind::Plugin => {
+            let (message, perfdata) = parse_plugin_output(&String::from_utf8_lossy(&output.stdout));
+            Ok(Outcome {
+                status,
+                message,
+                perfdata,
+                report,
+            })
         }
-        Err(e) => {
-            result.errors.push(Box::new(e));
-            return result;
+        Ok(status) => Ok(Outcome {
+            status,
+            message: exited_skipped.then(|| format!("exit code {SKIP_EXIT_CODE}")),
+            perfdata: vec![],
+            report,
+        }),
+        Err(timeout) => Err(Box::new(CheckError::TimedOut {
+            kind: kind.to_string(),
+            path: entry.to_string_lossy().into_owned(),
+            timeout,
+            attempts: attempt,
+            report,
+        })),
+    }
+}
+
+/// Runs `task` on every item, at most `limit` at a time, and returns the
+/// results in item order
+fn run_parallel<T: Sync, R: Send>(
+    items: &[T],
+    limit: usize,
+    task: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    if items.len() <= 1 || limit <= 1 {
+        return items.iter().map(task).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<R>>>());
+    thread::scope(|scope| {
+        for _ in 0..limit.min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    let result = task(item);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
         }
-    };
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is run"))
+        .collect()
+}
 
-    for entry in entries.flatten() {
-        // Process script/binary name
-        let file_name = match entry.file_name().and_then(|n| n.to_str()) {
-            Some(name) => name,
-            None => continue,
-        };
+fn run_scripts(kind: CheckKind, entries: Vec<CheckEntry>, policy: &CheckPolicy) -> ScriptRunResult {
+    let name = kind.as_str();
+    let mut result = ScriptRunResult {
+        errors: Vec::new(),
+        disabled: Vec::new(),
+        results: Vec::new(),
+    };
 
+    let known = entries.clone();
+    let mut runnable = vec![];
+    for entry in entries {
         // Check if script/binary should be skipped
-        if let Some(disabled) = disabled_scripts
-            && disabled.contains(&file_name.to_string())
-        {
-            log::info!("Skipping disabled script: {file_name}");
-            result.skipped.push(file_name.to_string());
+        if entry.disabled {
+            log::info!("Skipping disabled script: {}", entry.name);
+            result
+                .results
+                .push(CheckResult::new(&entry, CheckStatus::Disabled, None, 0));
+            result.disabled.push(entry.name);
+            continue;
+        }
+        if entry.masked {
+            log::info!("Skipping masked script: {}", entry.name);
+            continue;
+        }
+        if entry.lacks_exec_bit() {
             continue;
         }
+        if let Some(vendor) = &entry.overrides {
+            log::info!("{} overrides {}", entry.path.display(), vendor.display());
+        }
+        runnable.push(entry);
+    }
 
-        log::info!("running {} check {}", name, entry.to_string_lossy());
+    let mut succeeded: HashSet<String> = HashSet::new();
+    for batch in policy.batches(kind, runnable) {
+        let mut failed = false;
+        // Requires= on a check of this directory that did not succeed, be it
+        // failed, disabled or masked, fails the dependent check unrun
+        let (batch, unmet): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|entry| unmet_requirement(entry, &known, &succeeded).is_none());
+        for entry in unmet {
+            let dependency = unmet_requirement(&entry, &known, &succeeded).unwrap_or_default();
+            let error = CheckError::DependencyFailed {
+                kind: name.to_string(),
+                path: entry.path.to_string_lossy().into_owned(),
+                dependency,
+            };
+            result.results.push(CheckResult::new(
+                &entry,
+                CheckStatus::Failed,
+                Some(error.to_string()),
+                0,
+            ));
+            result.errors.push(Box::new(error));
+            failed = true;
+        }
+        // Checks not applicable here count as succeeded for Requires=, like
+        // units skipped by a condition in systemd
+        let (batch, not_applicable): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|entry| entry.unmet_condition().is_none());
+        for entry in not_applicable {
+            let reason = entry
+                .unmet_condition()
+                .map(|condition| format!("{condition} not met"));
+            log::info!(
+                "{} script {} skipped (condition): {}",
+                name,
+                entry.describe(),
+                reason.as_deref().unwrap_or_default()
+            );
+            result
+                .results
+                .push(CheckResult::new(&entry, CheckStatus::Skipped, reason, 0));
+            succeeded.insert(entry.name);
+        }
 
-        // Sort between scripts and binaries since they require different commands to execute properly.
-        let output = if entry.extension().and_then(|ext| ext.to_str()) == Some("sh") {
-            Command::new("bash").arg("-C").arg(&entry).output()
-        } else {
-            Command::new(&entry).output()
-        };
+        if let [entry] = batch.as_slice() {
+            log::info!("running {} check {}", name, entry.describe());
+        } else if !batch.is_empty() {
+            log::info!(
+                "running {} {name} checks in parallel: {}",
+                batch.len(),
+                batch
+                    .iter()
+                    .map(CheckEntry::describe)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        let outcomes = run_parallel(&batch, policy.parallelism, |entry| {
+            run_script(kind, entry, policy)
+        });
 
-        match output {
-            Ok(o) if o.status.success() => {
-                log::info!("{} script {} success!", name, entry.to_string_lossy());
-            }
-            Ok(o) => {
-                let error_msg = format!(
-                    "{} script {} failed!\n{}\n{}",
-                    name,
-                    entry.to_string_lossy(),
-                    String::from_utf8_lossy(&o.stdout),
-                    String::from_utf8_lossy(&o.stderr)
-                );
-                result
-                    .errors
-                    .push(Box::new(std::io::Error::other(error_msg)));
-                if name == "required" {
-                    break;
+        // Reported in run order, whichever check finished first
+        for (entry, outcome) in batch.iter().zip(outcomes) {
+            let (outcome, attempts) = match outcome {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    let status = match e.downcast_ref::<CheckError>() {
+                        Some(CheckError::TimedOut { .. }) => CheckStatus::TimedOut,
+                        _ => CheckStatus::Failed,
+                    };
+                    let mut check_result = CheckResult::new(
+                        entry,
+                        status,
+                        Some(e.to_string()),
+                        e.downcast_ref::<CheckError>()
+                            .map_or(1, CheckError::attempts),
+                    );
+                    // CRITICAL plugins report why in their status line
+                    if let (CheckKind::Plugin, Some(CheckError::Failed { stdout, .. })) =
+                        (kind, e.downcast_ref::<CheckError>())
+                    {
+                        let (message, perfdata) = parse_plugin_output(stdout);
+                        check_result.message = message.or(check_result.message);
+                        check_result.perfdata = perfdata;
+                    }
+                    if let Some(report) =
+                        e.downcast_ref::<CheckError>().and_then(CheckError::report)
+                    {
+                        check_result.merge(report.clone());
+                    }
+                    if let Some(hint) = &check_result.hint {
+                        log::error!("{} script {} hint: {hint}", name, entry.describe());
+                    }
+                    result.results.push(check_result);
+                    result.errors.push(e);
+                    failed = true;
+                    continue;
                 }
-            }
-            Err(e) => {
-                result.errors.push(Box::new(e));
-                if name == "required" {
-                    break;
+            };
+            let mut check_result = CheckResult {
+                perfdata: outcome.perfdata,
+                ..CheckResult::new(entry, outcome.status, outcome.message, attempts)
+            };
+            check_result.merge(outcome.report);
+            let message = check_result.message.as_deref().unwrap_or_default();
+            match (outcome.status, attempts) {
+                (CheckStatus::Skipped, _) => {
+                    log::info!("{} script {} skipped ({message})", name, entry.describe())
                 }
+                (CheckStatus::Warning, _) => {
+                    log::warn!("{} script {} warning: {message}", name, entry.describe())
+                }
+                (_, 1) => log::info!("{} script {} success!", name, entry.describe()),
+                (_, attempts) => log::info!(
+                    "{} script {} success after {attempts} attempts!",
+                    name,
+                    entry.describe()
+                ),
+            }
+            if let Some(hint) = &check_result.hint {
+                log::info!("{} script {} hint: {hint}", name, entry.describe());
+            }
+            result.results.push(check_result);
+            if outcome.status != CheckStatus::Warning {
+                succeeded.insert(entry.name.clone());
             }
         }
+        if failed && kind.fails_boot() {
+            break;
+        }
     }
 
+    // disabled and unrun checks were recorded first
+    result
+        .results
+        .sort_by_key(|check| known.iter().position(|entry| entry.name == check.name));
     result
 }
 
+/// first check of `known` required by `entry` that is not in `succeeded`
+fn unmet_requirement(
+    entry: &CheckEntry,
+    known: &[CheckEntry],
+    succeeded: &HashSet<String>,
+) -> Option<String> {
+    entry.meta.requires.iter().find_map(|dep| {
+        known
+            .iter()
+            .find(|other| other.answers_to(dep))
+            .filter(|other| !succeeded.contains(&other.name))
+            .map(|other| other.name.clone())
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -194,6 +2184,7 @@ mod test {
     use std::io::Write;
     use std::sync::Once;
     use std::{fs, os::unix::fs::PermissionsExt};
+    use tempfile::{TempDir, tempdir};
 
     static INIT: Once = Once::new();
 
@@ -203,18 +2194,33 @@ mod test {
         });
     }
 
-    static GREENBOOT_INSTALL_PATHS: [&str; 2] = ["/usr/lib/greenboot", "/etc/greenboot"];
+    /// greenboot's install paths below `root`, so tests never touch the real system
+    fn test_install_paths(root: &TempDir) -> Vec<String> {
+        GREENBOOT_INSTALL_PATHS
+            .iter()
+            .map(|path| {
+                root.path()
+                    .join(path.trim_start_matches('/'))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
 
     /// validate when the required folder is not found
     #[test]
     fn test_missing_required_folder() {
-        for path in GREENBOOT_INSTALL_PATHS {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        for path in &install_paths {
             let required_path = format!("{path}/check/required.d");
             if Path::new(&required_path).exists() {
                 fs::remove_dir_all(&required_path).unwrap();
             }
             assert_eq!(
-                run_diagnostics(vec![]).unwrap_err().to_string(),
+                run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default())
+                    .unwrap_err()
+                    .to_string(),
                 String::from("cannot find any required.d folder")
             );
         }
@@ -222,22 +2228,33 @@ mod test {
 
     #[test]
     fn test_passed_diagnostics() {
-        setup_folder_structure(true)
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        setup_folder_structure(&install_paths, true)
             .context("Test setup failed")
             .unwrap();
-        let state = run_diagnostics(vec![]);
+        let state = run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default());
         assert!(state.is_ok());
-        tear_down().context("Test teardown failed").unwrap();
     }
 
     #[test]
     fn test_required_script_failure_exit_early() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
         init_logger();
-        setup_folder_structure(false)
+        setup_folder_structure(&install_paths, false)
             .context("Test setup failed")
             .unwrap();
 
-        for base_path in GREENBOOT_INSTALL_PATHS {
+        for base_path in &install_paths {
+            // Scripts in /etc override those of the same name in /usr/lib, so
+            // only base_path may hold the failing scripts
+            for other_path in install_paths.iter().filter(|p| *p != base_path) {
+                for name in ["01_failing_script", "02_failing_script"] {
+                    let _ = fs::remove_file(format!("{other_path}/check/required.d/{name}.sh"));
+                }
+            }
+
             // Causes errors if these are not removed since they cause an excess amount
             // of failures.
             let _ = std::fs::remove_file(format!("{base_path}/01_failing_binary"));
@@ -258,7 +2275,7 @@ mod test {
                 std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
             }
 
-            let result = run_diagnostics(vec![]);
+            let result = run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default());
             log::debug!("Diagnostics result: {result:?}");
 
             assert!(result.is_err());
@@ -286,36 +2303,43 @@ mod test {
                     .expect("Failed to remove script file");
             }
         }
-
-        tear_down().expect("teardown failed");
     }
 
     #[test]
     fn test_skip_nonexistent_script() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
         let nonexistent_script_name = "nonexistent_script.sh".to_string();
-        setup_folder_structure(true)
+        setup_folder_structure(&install_paths, true)
             .context("Test setup failed")
             .unwrap();
 
         // Try to run a script that doesn't exist
-        let state = run_diagnostics(vec![nonexistent_script_name.clone()]);
+        let state = run_diagnostics_in(
+            &install_paths,
+            vec![nonexistent_script_name.clone()],
+            &CheckPolicy::default(),
+        );
         assert!(
-            state.unwrap().contains(&nonexistent_script_name),
+            state
+                .unwrap()
+                .missing_disabled
+                .contains(&nonexistent_script_name),
             "non existent script names did not match"
         );
-
-        tear_down().context("Test teardown failed").unwrap();
     }
 
     #[test]
     fn test_skip_disabled_script() {
-        setup_folder_structure(false)
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        setup_folder_structure(&install_paths, false)
             .context("Test setup failed")
             .unwrap();
 
         // Removing extra failing binaries because this can cause a
         // failure if not added to the skips or removed as done below.
-        for base_path in GREENBOOT_INSTALL_PATHS {
+        for base_path in &install_paths {
             let required_path = format!("{base_path}/check/required.d");
             let _ = std::fs::remove_file(format!("{required_path}/01_failing_binary"));
             let _ = std::fs::remove_file(format!("{required_path}/02_failing_binary"));
@@ -323,29 +2347,33 @@ mod test {
 
         // Skip the disabled script in required.d ,since there are two
         // failing- scripts passing them both so that this test passes.
-        let state = run_diagnostics(vec![
-            "01_failing_script.sh".to_string(),
-            "02_failing_script.sh".to_string(),
-        ]);
+        let state = run_diagnostics_in(
+            &install_paths,
+            vec![
+                "01_failing_script.sh".to_string(),
+                "02_failing_script.sh".to_string(),
+            ],
+            &CheckPolicy::default(),
+        );
         assert!(
             state.is_ok(),
             "Should pass when skipping disabled required script"
         );
-
-        tear_down().context("Test teardown failed").unwrap();
     }
 
     // Since binaries are a separate and later added feature compared to
     // scripts, there should be a separate test to ensure they both work.
     #[test]
     fn test_skip_disabled_binary() {
-        setup_folder_structure(false)
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        setup_folder_structure(&install_paths, false)
             .context("Test setup failed")
             .unwrap();
 
         // Removing extra failing scripts because this can cause a
         // failure if not added to the skips or removed as done below
-        for base_path in GREENBOOT_INSTALL_PATHS {
+        for base_path in &install_paths {
             let required_path = format!("{base_path}/check/required.d");
             let _ = std::fs::remove_file(format!("{required_path}/01_failing_script.sh"));
             let _ = std::fs::remove_file(format!("{required_path}/02_failing_script.sh"));
@@ -353,25 +2381,952 @@ mod test {
 
         // Skip the disabled script in required.d ,since there are two
         // failing- scripts passing them both so that this test passes.
-        let state = run_diagnostics(vec![
-            "01_failing_binary".to_string(),
-            "02_failing_binary".to_string(),
-        ]);
+        let state = run_diagnostics_in(
+            &install_paths,
+            vec![
+                "01_failing_binary".to_string(),
+                "02_failing_binary".to_string(),
+            ],
+            &CheckPolicy::default(),
+        );
         assert!(
             state.is_ok(),
             "Should pass when skipping disabled required binary"
         );
+    }
+
+    #[test]
+    fn test_list_checks() {
+        let root = tempfile::tempdir().unwrap();
+        let usr = root.path().join("usr");
+        let etc = root.path().join("etc");
+        fs::create_dir_all(usr.join("check/required.d")).unwrap();
+        fs::create_dir_all(etc.join("check/wanted.d")).unwrap();
+        fs::create_dir_all(usr.join("red.d")).unwrap();
+        fs::copy(
+            "testing_assets/passing_script.sh",
+            usr.join("check/required.d/00_script.sh"),
+        )
+        .unwrap();
+        fs::copy(
+            "testing_assets/passing_binary",
+            etc.join("check/wanted.d/01_binary"),
+        )
+        .unwrap();
+        fs::write(usr.join("red.d/no_exec"), "").unwrap();
+        fs::set_permissions(usr.join("red.d/no_exec"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let inventory = list_checks_in(
+            &[&usr, &etc],
+            &["01_binary".to_string(), "typo.sh".to_string()],
+        );
+        let summary: Vec<_> = inventory
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.kind, e.runner, e.disabled, e.will_run()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "00_script.sh",
+                    CheckKind::Required,
+                    CheckRunner::Script,
+                    false,
+                    true
+                ),
+                (
+                    "01_binary",
+                    CheckKind::Wanted,
+                    CheckRunner::Binary,
+                    true,
+                    false
+                ),
+                ("no_exec", CheckKind::Red, CheckRunner::Binary, false, false),
+            ]
+        );
+        assert_eq!(inventory.entries[1].source, etc);
+        assert!(inventory.entries[2].lacks_exec_bit());
+        assert_eq!(inventory.unmatched_disabled, vec!["typo.sh"]);
+    }
+
+    #[test]
+    fn test_etc_overrides_and_masks_usr() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        let [usr, etc] = [0, 1].map(|i| format!("{}/check/required.d", install_paths[i]));
+        fs::create_dir_all(&usr).unwrap();
+        fs::create_dir_all(&etc).unwrap();
+        fs::write(format!("{usr}/01_vendor.sh"), "exit 1").unwrap();
+        fs::write(format!("{usr}/02_vendor.sh"), "exit 1").unwrap();
+        fs::write(format!("{usr}/04_vendor.sh"), "exit 0").unwrap();
+        fs::write(format!("{etc}/01_vendor.sh"), "exit 0").unwrap();
+        std::os::unix::fs::symlink("/dev/null", format!("{etc}/02_vendor.sh")).unwrap();
+        fs::write(format!("{etc}/03_local.sh"), "exit 0").unwrap();
+
+        let entries = kind_entries_in(&install_paths, CheckKind::Required, &[]);
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.name.as_str(),
+                    e.masked,
+                    e.overrides.is_some(),
+                    e.will_run(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("01_vendor.sh", false, true, true),
+                ("02_vendor.sh", true, true, false),
+                ("03_local.sh", false, false, true),
+                ("04_vendor.sh", false, false, true),
+            ]
+        );
+        assert_eq!(entries[0].path, Path::new(&etc).join("01_vendor.sh"));
+        assert_eq!(
+            entries[0].overrides.as_deref(),
+            Some(Path::new(&usr).join("01_vendor.sh").as_path())
+        );
+        assert!(run_check(&entries[1], &CheckPolicy::default()).is_err());
+
+        assert!(run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn test_run_check() {
+        let root = tempfile::tempdir().unwrap();
+        let required = root.path().join("check/required.d");
+        fs::create_dir_all(&required).unwrap();
+        fs::copy("testing_assets/passing_script.sh", required.join("pass.sh")).unwrap();
+        fs::copy("testing_assets/failing_binary", required.join("fail")).unwrap();
+
+        let inventory = list_checks_in(&[root.path()], &[]);
+        let check = |name: &str| {
+            let entry = inventory.entries.iter().find(|e| e.name == name).unwrap();
+            run_check(entry, &CheckPolicy::default())
+        };
+        assert!(check("pass.sh").is_ok());
+        assert!(check("fail").unwrap_err().to_string().contains("failed"));
+    }
+
+    #[test]
+    fn test_required_check_timeout() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        let required = format!("{}/check/required.d", install_paths[1]);
+        fs::create_dir_all(&required).unwrap();
+        // The background sleep keeps the output pipes open, so only killing
+        // the whole process group lets greenboot continue
+        fs::write(format!("{required}/01_hang.sh"), "sleep 60 &\nsleep 60\n").unwrap();
+
+        let mut policy = CheckPolicy::default();
+        policy.timeouts.default = Some(Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let err = run_diagnostics_in(&install_paths, vec![], &policy).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert_eq!(
+            err.to_string(),
+            "required health-check timed out, skipping remaining scripts"
+        );
+        // recorded apart from a failure, also once saved and loaded again
+        let diagnostics = err.diagnostics().unwrap();
+        let hung = &diagnostics.results[0];
+        assert_eq!(hung.status, CheckStatus::TimedOut);
+        assert_eq!(
+            CheckResult::from_json(&hung.to_json()).unwrap().status,
+            CheckStatus::TimedOut
+        );
+        assert_eq!(
+            diagnostics.summary(),
+            "Timed out required checks: 01_hang.sh"
+        );
+
+        fs::write(format!("{required}/01_hang.sh"), "sleep 1\n").unwrap();
+        policy
+            .timeouts
+            .overrides
+            .insert(String::from("01_hang.sh"), None);
+        assert!(run_diagnostics_in(&install_paths, vec![], &policy).is_ok());
+    }
+
+    #[test]
+    fn test_timeout_with_detached_descendant() {
+        // setsid moves the sleep out of the process group, so killing the
+        // group leaves it holding the output pipes
+        let started = Instant::now();
+        let output = run_with_timeout(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo started; setsid sleep 20 & sleep 20")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+            Some(Duration::from_millis(500)),
+            false,
+        )
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(15));
+        assert_eq!(output.status.unwrap_err(), Duration::from_millis(500));
+        assert_eq!(output.stdout, b"started\n");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(DEFAULT_CHECK_TIMEOUT), "300s");
+        assert_eq!(format_duration(Duration::from_millis(500)), "0.5s");
+        let err = CheckError::TimedOut {
+            kind: String::from("required"),
+            path: String::from("01_hang.sh"),
+            timeout: Duration::from_millis(1500),
+            attempts: 1,
+            report: CheckReport::default(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "required script 01_hang.sh timed out after 1.5s and was killed"
+        );
+    }
+
+    #[test]
+    fn test_detached_process_group() {
+        // process group of the command and of greenboot, its parent
+        for foreground in [false, true] {
+            let output = run_with_timeout(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("cut -d' ' -f5 /proc/$$/stat /proc/$PPID/stat")
+                    .stdout(Stdio::piped()),
+                None,
+                foreground,
+            )
+            .unwrap();
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            let pgids: Vec<&str> = stdout.lines().collect();
+            assert_eq!(pgids.len(), 2);
+            assert_ne!(pgids[0], pgids[1]);
+        }
+    }
+
+    #[test]
+    fn test_run_check_timeout_kills_children() {
+        let root = tempdir().unwrap();
+        let required = root.path().join("check/required.d");
+        fs::create_dir_all(&required).unwrap();
+        let pid_file = root.path().join("child.pid");
+        fs::write(
+            required.join("01_hang.sh"),
+            format!("sleep 60 &\necho $! >| {}\nsleep 60\n", pid_file.display()),
+        )
+        .unwrap();
+
+        let mut policy = CheckPolicy::default();
+        policy.timeouts.default = Some(Duration::from_millis(500));
+        let inventory = list_checks_in(&[root.path()], &[]);
+        let err = run_check(&inventory.entries[0], &policy).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CheckError>(),
+            Some(CheckError::TimedOut { .. })
+        ));
+        // the background sleep went down with the check, at most unreaped
+        let child = fs::read_to_string(&pid_file).unwrap();
+        let state = fs::read_to_string(format!("/proc/{}/stat", child.trim())).unwrap_or_default();
+        let state = state.rsplit(") ").next().unwrap_or_default();
+        assert!(state.is_empty() || state.starts_with('Z'), "{state}");
+    }
+
+    #[test]
+    fn test_wanted_checks_run_in_parallel() {
+        let root = tempdir().unwrap();
+        let wanted = root.path().join("check/wanted.d");
+        let markers = root.path().join("markers");
+        fs::create_dir_all(&wanted).unwrap();
+        fs::create_dir_all(&markers).unwrap();
+        // each waiting check leaves a marker and passes only once all three
+        // are there, which never happens if they run one after another
+        let markers = markers.display();
+        let waiting = format!(
+            "touch {markers}/$(basename $0)\n\
+             for _ in $(seq 300); do\n\
+             [ -e {markers}/01_wait.sh ] && [ -e {markers}/02_wait.sh ] && [ -e {markers}/03_wait.sh ] && exit 0\n\
+             sleep 0.1\n\
+             done\n\
+             exit 1\n"
+        );
+        for name in ["01_wait.sh", "02_wait.sh", "03_wait.sh"] {
+            fs::write(wanted.join(name), &waiting).unwrap();
+        }
+        fs::write(wanted.join("04_fail.sh"), "echo broken\nexit 1\n").unwrap();
+        fs::write(wanted.join("00_fail.sh"), "exit 1\n").unwrap();
+
+        let policy = CheckPolicy::default();
+        let entries = kind_entries_in(&[root.path()], CheckKind::Wanted, &[]);
+        let result = run_scripts(CheckKind::Wanted, entries, &policy);
+        let errors: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors[0].contains("00_fail.sh"));
+        assert!(errors[1].contains("04_fail.sh") && errors[1].contains("broken"));
+    }
+
+    /// a wanted check called `name` running after the checks in `after`
+    fn test_entry(name: &str, after: &[&str]) -> CheckEntry {
+        CheckEntry {
+            name: name.to_string(),
+            kind: CheckKind::Wanted,
+            source: PathBuf::from("/usr/lib/greenboot"),
+            path: PathBuf::from(format!("/usr/lib/greenboot/check/wanted.d/{name}")),
+            runner: CheckRunner::Script,
+            executable: true,
+            disabled: false,
+            masked: false,
+            overrides: None,
+            meta: CheckMeta {
+                after: after.iter().map(|dep| dep.to_string()).collect(),
+                ..CheckMeta::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_check_policy_to_json() {
+        let mut policy = CheckPolicy {
+            independent: vec![String::from("01_a.sh")],
+            plugin_unknown: CheckStatus::Failed,
+            ..CheckPolicy::default()
+        };
+        policy
+            .timeouts
+            .overrides
+            .insert(String::from("02_b.sh"), None);
+        policy.retries.insert(
+            String::from("03_c.sh"),
+            RetryPolicy {
+                attempts: 3,
+                ..RetryPolicy::default()
+            },
+        );
+        assert_eq!(
+            policy.to_json(),
+            json!({
+                "check_timeout": 300,
+                "check_timeouts": {"02_b.sh": 0},
+                "check_parallelism": DEFAULT_CHECK_PARALLELISM,
+                "independent_checks": ["01_a.sh"],
+                "check_retries": {"03_c.sh": {"attempts": 3, "delay": 5, "backoff": 1}},
+                "plugin_unknown": "failed",
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_policy_batches() {
+        let entries = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| test_entry(name, &[]))
+                .collect::<Vec<_>>()
+        };
+        let names = ["01_a.sh", "02_b.sh", "03_c.sh", "04_d.sh"];
+        let policy = CheckPolicy {
+            independent: vec![String::from("01_a.sh"), String::from("02_b.sh")],
+            ..CheckPolicy::default()
+        };
+        assert_eq!(
+            policy.batches(CheckKind::Required, entries(&names)),
+            vec![
+                entries(&["01_a.sh", "02_b.sh"]),
+                entries(&["03_c.sh"]),
+                entries(&["04_d.sh"])
+            ]
+        );
+        assert_eq!(
+            policy.batches(CheckKind::Plugin, entries(&names)),
+            policy.batches(CheckKind::Required, entries(&names))
+        );
+        assert_eq!(
+            policy.batches(CheckKind::Wanted, entries(&names)),
+            vec![entries(&names)]
+        );
+        assert_eq!(policy.batches(CheckKind::Green, entries(&names)).len(), 4);
+
+        let mut dependent = entries(&names);
+        dependent[2] = test_entry("03_c.sh", &["02_b"]);
+        let batches = policy.batches(CheckKind::Wanted, dependent);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1][0].name, "03_c.sh");
+
+        let sequential = CheckPolicy {
+            parallelism: 1,
+            ..policy
+        };
+        assert_eq!(
+            sequential.batches(CheckKind::Wanted, entries(&names)).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_parse_meta_header() {
+        let (meta, problems) = CheckMeta::parse_header(
+            "#!/bin/bash\n\
+             # greenboot: Description=Checks DNS resolution Timeout=1m\n\
+             # greenboot: After=01_dns.sh 02_ntp Requires=network Color=red\n\
+             #\n\
+             echo ok\n\
+             # greenboot: Timeout=5s\n",
+        );
+        assert_eq!(
+            meta,
+            CheckMeta {
+                description: Some(String::from("Checks DNS resolution")),
+                timeout: Some(Some(Duration::from_secs(60))),
+                after: vec![String::from("01_dns.sh"), String::from("02_ntp")],
+                requires: vec![String::from("network")],
+                conditions: vec![],
+            }
+        );
+        assert_eq!(problems, vec!["unknown key Color"]);
+
+        let (meta, problems) = CheckMeta::parse_header("# greenboot: Timeout=soon\n");
+        assert_eq!(meta.timeout, None);
+        assert_eq!(problems, vec!["invalid Timeout=soon"]);
+
+        assert_eq!(parse_timeout("30"), Some(Some(Duration::from_secs(30))));
+        assert_eq!(parse_timeout("2h"), Some(Some(Duration::from_secs(7200))));
+        assert_eq!(parse_timeout("infinity"), Some(None));
+        assert_eq!(parse_timeout("0s"), Some(None));
+        assert_eq!(parse_timeout("s"), None);
+    }
+
+    #[test]
+    fn test_order_entries() {
+        let names = |entries: &[CheckEntry]| {
+            entries
+                .iter()
+                .map(|entry| entry.name.clone())
+                .collect::<Vec<_>>()
+        };
+        let (ordered, problems) = order_entries(
+            vec![
+                test_entry("01_a.sh", &["03_c.sh"]),
+                test_entry("02_b.sh", &["00_required"]),
+                test_entry("03_c.sh", &[]),
+                test_entry("04_d.sh", &["typo.sh"]),
+            ],
+            &[test_entry("00_required.sh", &[])],
+        );
+        assert_eq!(
+            names(&ordered),
+            ["02_b.sh", "03_c.sh", "01_a.sh", "04_d.sh"]
+        );
+        assert_eq!(
+            problems,
+            vec!["wanted check 04_d.sh depends on unknown check typo.sh"]
+        );
+
+        let (ordered, problems) = order_entries(
+            vec![
+                test_entry("01_a.sh", &["02_b.sh"]),
+                test_entry("02_b.sh", &["01_a.sh"]),
+                test_entry("03_c.sh", &[]),
+            ],
+            &[],
+        );
+        assert_eq!(names(&ordered), ["03_c.sh", "01_a.sh", "02_b.sh"]);
+        assert_eq!(
+            problems,
+            vec![
+                "wanted checks 01_a.sh, 02_b.sh cannot be ordered, their dependencies form a cycle"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_conditions() {
+        let (meta, problems) = CheckMeta::parse_header(
+            "# greenboot: ConditionPathExists=!/run/ostree-booted\n\
+             # greenboot: ConditionArchitecture=x86-64 ConditionKernelCommandLine=\n",
+        );
+        assert_eq!(
+            meta.conditions
+                .iter()
+                .map(Condition::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "ConditionPathExists=!/run/ostree-booted",
+                "ConditionArchitecture=x86-64"
+            ]
+        );
+        assert_eq!(problems, vec!["empty ConditionKernelCommandLine="]);
+        assert_eq!(snake_case("ConditionPathExists"), "condition_path_exists");
+
+        assert!(architecture_is("x86_64", false, "x86-64"));
+        assert!(architecture_is("aarch64", false, "aarch64"));
+        assert!(architecture_is("aarch64", false, "arm64"));
+        assert!(!architecture_is("aarch64", true, "arm64"));
+        assert!(!architecture_is("aarch64", false, "x86-64"));
+        assert!(architecture_is("powerpc64", false, "ppc64-le"));
+        assert!(!architecture_is("powerpc64", true, "ppc64-le"));
+        assert!(architecture_is("powerpc64", true, "ppc64"));
+        assert!(!architecture_is("powerpc64", false, "ppc64"));
+        assert!(architecture_is("s390x", true, "s390x"));
+        assert!(architecture_is("riscv64", false, "riscv64"));
+        assert!(architecture_is("x86", false, "x86"));
+        assert!(!architecture_is("x86", false, "x86-64"));
+        assert!(virtualization_is("kvm", false, "vm"));
+        assert!(virtualization_is("kvm", false, "kvm"));
+        assert!(!virtualization_is("podman", true, "vm"));
+        assert!(virtualization_is("podman", true, "yes"));
+        assert!(virtualization_is("none", false, "no"));
+        let cmdline = "BOOT_IMAGE=/vmlinuz ro quiet console=ttyS0\n";
+        assert!(cmdline_has(cmdline, "quiet"));
+        assert!(cmdline_has(cmdline, "console"));
+        assert!(cmdline_has(cmdline, "console=ttyS0"));
+        assert!(!cmdline_has(cmdline, "console=tty0"));
+        assert!(!cmdline_has(cmdline, "rw"));
+    }
+
+    #[test]
+    fn test_unmet_condition_skips_check() {
+        let root = tempdir().unwrap();
+        let required = root.path().join("check/required.d");
+        fs::create_dir_all(&required).unwrap();
+        let missing = root.path().join("watchdog");
+        fs::write(
+            required.join("01_watchdog.sh"),
+            format!(
+                "# greenboot: ConditionPathExists={}\nexit 1\n",
+                missing.display()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            required.join("02_after_watchdog.sh"),
+            "# greenboot: Requires=01_watchdog ConditionPathExists=!/nonexistent\nexit 0\n",
+        )
+        .unwrap();
+
+        let entries = kind_entries_in(&[root.path()], CheckKind::Required, &[]);
+        let unmet = entries[0].unmet_condition().map(Condition::to_string);
+        assert_eq!(
+            unmet,
+            Some(format!("ConditionPathExists={}", missing.display()))
+        );
+        assert!(entries[1].unmet_condition().is_none());
+        assert_eq!(
+            run_check(&entries[0], &CheckPolicy::default()).unwrap(),
+            CheckStatus::Skipped
+        );
+        let result = run_scripts(CheckKind::Required, entries, &CheckPolicy::default());
+        assert!(result.errors.is_empty());
+
+        fs::write(&missing, "").unwrap();
+        let entries = kind_entries_in(&[root.path()], CheckKind::Required, &[]);
+        let result = run_scripts(CheckKind::Required, entries, &CheckPolicy::default());
+        assert_eq!(result.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_skip_exit_code() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        let [required, wanted] =
+            ["required", "wanted"].map(|kind| format!("{}/check/{kind}.d", install_paths[1]));
+        fs::create_dir_all(&required).unwrap();
+        fs::create_dir_all(&wanted).unwrap();
+        fs::write(format!("{required}/01_not_here.sh"), "exit 77\n").unwrap();
+        fs::write(format!("{required}/02_pass.sh"), "exit 0\n").unwrap();
+        fs::write(format!("{wanted}/01_fail.sh"), "exit 1\n").unwrap();
+        fs::write(format!("{wanted}/02_off.sh"), "exit 1\n").unwrap();
+
+        let diagnostics = run_diagnostics_in(
+            &install_paths,
+            vec![String::from("02_off.sh")],
+            &CheckPolicy::default(),
+        )
+        .unwrap();
+        let statuses: Vec<_> = diagnostics
+            .results
+            .iter()
+            .map(|result| (result.name.as_str(), result.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("01_not_here.sh", CheckStatus::Skipped),
+                ("02_pass.sh", CheckStatus::Passed),
+                ("01_fail.sh", CheckStatus::Failed),
+                ("02_off.sh", CheckStatus::Disabled),
+            ]
+        );
+        assert_eq!(
+            diagnostics.results[0].message.as_deref(),
+            Some("exit code 77")
+        );
+        assert_eq!(
+            diagnostics.summary(),
+            "Failed wanted checks: 01_fail.sh\nSkipped checks: 01_not_here.sh"
+        );
+
+        fs::write(format!("{required}/02_pass.sh"), "exit 1\n").unwrap();
+        let err = run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).unwrap_err();
+        let diagnostics = err.diagnostics().unwrap();
+        assert_eq!(diagnostics.names(CheckStatus::Failed), ["02_pass.sh"]);
+        assert_eq!(diagnostics.results.len(), 2);
+        let result = &diagnostics.results[1];
+        assert_eq!(
+            CheckResult::from_json(&result.to_json()).as_ref(),
+            Some(result)
+        );
+    }
+
+    #[test]
+    fn test_parse_plugin_output() {
+        let (message, perfdata) = parse_plugin_output(
+            "DISK OK - free space: / 3326 MB (56%); | /=2643MB;5948;5958;0;5968\n\
+             / 15272 MB (77%);\n\
+             /boot 68 MB (69%); | /boot=68MB;88;93;0;98\n\
+             'home dir'=U;;;; load=0.75",
+        );
+        assert_eq!(
+            message.as_deref(),
+            Some("DISK OK - free space: / 3326 MB (56%);")
+        );
+        assert_eq!(
+            perfdata[0],
+            PerfData {
+                label: String::from("/"),
+                value: Some(2643.0),
+                unit: String::from("MB"),
+                warn: Some(String::from("5948")),
+                crit: Some(String::from("5958")),
+                min: Some(0.0),
+                max: Some(5968.0),
+            }
+        );
+        let labels: Vec<_> = perfdata.iter().map(|p| p.label.as_str()).collect();
+        assert_eq!(labels, ["/", "/boot", "home dir", "load"]);
+        assert_eq!(perfdata[2].value, None);
+        assert_eq!(
+            (perfdata[3].value, perfdata[3].unit.as_str()),
+            (Some(0.75), "")
+        );
+
+        assert_eq!(parse_plugin_output(""), (None, vec![]));
+        assert_eq!(parse_plugin_unknown("critical"), Some(CheckStatus::Failed));
+        assert_eq!(parse_plugin_unknown("ok"), None);
+    }
+
+    #[test]
+    fn test_plugin_checks() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        let [required, plugins] = ["check/required.d", "check/plugins.d"]
+            .map(|dir| format!("{}/{dir}", install_paths[1]));
+        fs::create_dir_all(&required).unwrap();
+        fs::create_dir_all(&plugins).unwrap();
+        fs::write(
+            format!("{plugins}/check_load.sh"),
+            "echo 'LOAD WARNING - load average: 9.1 | load1=9.1;5;10;0'\nexit 1\n",
+        )
+        .unwrap();
+        fs::write(
+            format!("{plugins}/check_procs.sh"),
+            "echo 'PROCS UNKNOWN - cannot read /proc'\nexit 3\n",
+        )
+        .unwrap();
+
+        let diagnostics =
+            run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).unwrap();
+        let load = &diagnostics.results[0];
+        assert_eq!(load.status, CheckStatus::Warning);
+        assert_eq!(
+            load.message.as_deref(),
+            Some("LOAD WARNING - load average: 9.1")
+        );
+        assert_eq!(load.perfdata[0].value, Some(9.1));
+        assert_eq!(diagnostics.results[1].status, CheckStatus::Warning);
+        assert!(
+            diagnostics
+                .summary()
+                .contains("Warning checks: check_load.sh, check_procs.sh")
+        );
+
+        let critical_unknown = CheckPolicy {
+            plugin_unknown: CheckStatus::Failed,
+            ..CheckPolicy::default()
+        };
+        let err = run_diagnostics_in(&install_paths, vec![], &critical_unknown).unwrap_err();
+        let failed = &err.diagnostics().unwrap().results[1];
+        assert_eq!(failed.status, CheckStatus::Failed);
+        assert_eq!(
+            failed.message.as_deref(),
+            Some("PROCS UNKNOWN - cannot read /proc")
+        );
+
+        fs::write(
+            format!("{plugins}/check_load.sh"),
+            "echo 'LOAD CRITICAL - load average: 31 | load1=31;5;10;0'\nexit 2\n",
+        )
+        .unwrap();
+        // like after a failed required check, no further plugin runs
+        let marker = root.path().join("procs-ran");
+        fs::write(
+            format!("{plugins}/check_procs.sh"),
+            format!("touch {}\necho 'PROCS OK'\n", marker.display()),
+        )
+        .unwrap();
+        let err = run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "plugin health-check failed, skipping remaining scripts"
+        );
+        let critical = &err.diagnostics().unwrap().results[0];
+        assert_eq!(critical.status, CheckStatus::Failed);
+        assert_eq!(critical.perfdata[0].value, Some(31.0));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_parse_check_report() {
+        let (report, problems) = CheckReport::parse(concat!(
+            "{\"status\": \"passed\", \"metrics\": {\"free_mb\": 310}}\n",
+            "\n",
+            "{\"status\": \"warning\", \"message\": \"disk almost full\",",
+            " \"metrics\": {\"used\": {\"value\": 97, \"unit\": \"%\", \"warn\": \"90\"}}}\n",
+            "{\"hint\": \"remove old deployments\", \"status\": \"disabled\"}\n",
+            "not json\n",
+        ));
+        assert_eq!(report.status, Some(CheckStatus::Warning));
+        assert_eq!(report.message.as_deref(), Some("disk almost full"));
+        assert_eq!(report.hint.as_deref(), Some("remove old deployments"));
+        assert_eq!(report.metrics.len(), 2);
+        assert_eq!(report.metrics[0].label, "free_mb");
+        assert_eq!(report.metrics[0].value, Some(310.0));
+        assert_eq!(report.metrics[1].unit, "%");
+        assert_eq!(report.metrics[1].warn.as_deref(), Some("90"));
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn test_check_result_channel() {
+        let root = tempdir().unwrap();
+        let install_paths = test_install_paths(&root);
+        let [required, wanted] =
+            ["check/required.d", "check/wanted.d"].map(|dir| format!("{}/{dir}", install_paths[1]));
+        fs::create_dir_all(&required).unwrap();
+        fs::create_dir_all(&wanted).unwrap();
+        fs::write(
+            format!("{wanted}/disk.sh"),
+            concat!(
+                "echo '{\"status\": \"warning\", \"message\": \"disk almost full\"}' >> \"$GREENBOOT_RESULT_FILE\"\n",
+                "echo '{\"metrics\": {\"used_pct\": 97}, \"hint\": \"remove old deployments\"}' >> \"$GREENBOOT_RESULT_FILE\"\n",
+                "exit 0\n",
+            ),
+        )
+        .unwrap();
+
+        let diagnostics =
+            run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).unwrap();
+        let disk = &diagnostics.results[0];
+        assert_eq!(disk.status, CheckStatus::Warning);
+        assert_eq!(disk.message.as_deref(), Some("disk almost full"));
+        assert_eq!(disk.perfdata[0].label, "used_pct");
+        assert_eq!(disk.hint.as_deref(), Some("remove old deployments"));
+        assert!(
+            diagnostics
+                .summary()
+                .contains("Hint for disk.sh: remove old deployments")
+        );
+        assert_eq!(CheckResult::from_json(&disk.to_json()).as_ref(), Some(disk));
+
+        // skipped through the channel, not by exit code 77
+        fs::write(
+            format!("{wanted}/disk.sh"),
+            "echo '{\"status\": \"skipped\"}' >> \"$GREENBOOT_RESULT_FILE\"\nexit 0\n",
+        )
+        .unwrap();
+        let diagnostics =
+            run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).unwrap();
+        assert_eq!(diagnostics.results[0].status, CheckStatus::Skipped);
+        assert_eq!(diagnostics.results[0].message, None);
+
+        // a failing check cannot report itself as passed
+        fs::write(
+            format!("{required}/broken.sh"),
+            "echo '{\"status\": \"passed\", \"hint\": \"restart foo\"}' >> \"$GREENBOOT_RESULT_FILE\"\nexit 1\n",
+        )
+        .unwrap();
+        let err = run_diagnostics_in(&install_paths, vec![], &CheckPolicy::default()).unwrap_err();
+        let broken = &err.diagnostics().unwrap().results[0];
+        assert_eq!(broken.status, CheckStatus::Failed);
+        assert_eq!(broken.hint.as_deref(), Some("restart foo"));
+    }
+
+    #[test]
+    fn test_check_metadata() {
+        let root = tempdir().unwrap();
+        let wanted = root.path().join("check/wanted.d");
+        fs::create_dir_all(&wanted).unwrap();
+        fs::write(
+            wanted.join("01_needs_dns.sh"),
+            "# greenboot: Requires=03_dns Timeout=infinity\nexit 0\n",
+        )
+        .unwrap();
+        fs::write(
+            wanted.join("02_slow.sh"),
+            "# greenboot: Description=Sleeps Timeout=1\nsleep 5\n",
+        )
+        .unwrap();
+        fs::write(wanted.join("03_dns.sh"), "exit 1\n").unwrap();
+        fs::copy("testing_assets/passing_binary", wanted.join("04_binary")).unwrap();
+        fs::write(
+            wanted.join("04_binary.toml"),
+            "description = \"Vendor check\"\ntimeout = 30\nafter = [\"01_needs_dns.sh\"]\n",
+        )
+        .unwrap();
+
+        let inventory = list_checks_in(&[root.path()], &[]);
+        let names: Vec<_> = inventory.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["02_slow.sh", "03_dns.sh", "01_needs_dns.sh", "04_binary"]
+        );
+        assert!(inventory.problems.is_empty());
+        let binary = &inventory.entries[3].meta;
+        assert_eq!(binary.description.as_deref(), Some("Vendor check"));
+        assert_eq!(binary.timeout, Some(Some(Duration::from_secs(30))));
+        assert_eq!(binary.after, vec!["01_needs_dns.sh"]);
+        assert_eq!(
+            CheckTimeouts::default().for_entry(&inventory.entries[2]),
+            None
+        );
+
+        let result = run_scripts(
+            CheckKind::Wanted,
+            inventory.entries,
+            &CheckPolicy::default(),
+        );
+        let errors: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("02_slow.sh timed out after 1s"));
+        assert!(errors[1].contains("03_dns.sh failed"));
+        assert!(
+            errors[2]
+                .ends_with("01_needs_dns.sh was not run, 03_dns.sh it requires did not succeed")
+        );
+    }
+
+    #[test]
+    fn test_run_parallel_keeps_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let results = run_parallel(&items, 3, |&i| {
+            thread::sleep(Duration::from_millis(20 - i));
+            i * 2
+        });
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_retry_flaky_check() {
+        let root = tempdir().unwrap();
+        let required = root.path().join("check/required.d");
+        fs::create_dir_all(&required).unwrap();
+        let counter = root.path().join("runs");
+        fs::write(&counter, "0").unwrap();
+        // Fails on the first two runs only
+        fs::write(
+            required.join("01_flaky.sh"),
+            format!(
+                "runs=$(($(cat {0}) + 1))\necho $runs >| {0}\necho run $runs\n[ $runs -ge 3 ]\n",
+                counter.display()
+            ),
+        )
+        .unwrap();
+        let run = |attempts| {
+            fs::write(&counter, "0").unwrap();
+            let mut policy = CheckPolicy::default();
+            policy.retries.insert(
+                String::from("01_flaky.sh"),
+                RetryPolicy {
+                    attempts,
+                    delay: Duration::ZERO,
+                    backoff: 2,
+                },
+            );
+            let entries = kind_entries_in(&[root.path()], CheckKind::Required, &[]);
+            run_scripts(CheckKind::Required, entries, &policy).errors
+        };
+
+        assert!(run(3).is_empty());
+        assert_eq!(fs::read_to_string(&counter).unwrap().trim(), "3");
+
+        let errors = run(2);
+        assert_eq!(errors.len(), 1);
+        let error = errors[0].downcast_ref::<CheckError>().unwrap();
+        assert_eq!(error.attempts(), 2);
+        assert!(error.to_string().contains("failed! (2 attempts)\nrun 2"));
+    }
+
+    #[test]
+    fn test_parse_retry_override() {
+        let retry = |attempts, delay, backoff| RetryPolicy {
+            attempts,
+            delay: Duration::from_secs(delay),
+            backoff,
+        };
+        assert_eq!(
+            parse_retry_override("01_repository_dns_check.sh:3:5:2"),
+            Some((String::from("01_repository_dns_check.sh"), retry(3, 5, 2)))
+        );
+        assert_eq!(
+            parse_retry_override("dns.sh:3"),
+            Some((String::from("dns.sh"), retry(3, 5, 1)))
+        );
+        assert_eq!(parse_retry_override("dns.sh"), None);
+        assert_eq!(parse_retry_override("dns.sh:0"), None);
+        assert_eq!(parse_retry_override("dns.sh:3:soon"), None);
+        assert_eq!(parse_retry_override("dns.sh:3:5:2:1"), None);
+
+        let backoff = retry(4, 5, 2);
+        assert_eq!(backoff.delay_after(1), Duration::from_secs(5));
+        assert_eq!(backoff.delay_after(3), Duration::from_secs(20));
+    }
 
-        tear_down().context("Test teardown failed").unwrap();
+    #[test]
+    fn test_parse_timeout_override() {
+        assert_eq!(
+            parse_timeout_override("01_update_platforms_check.sh:60"),
+            Some((
+                String::from("01_update_platforms_check.sh"),
+                Some(Duration::from_secs(60))
+            ))
+        );
+        assert_eq!(
+            parse_timeout_override("slow.sh:0"),
+            Some((String::from("slow.sh"), None))
+        );
+        assert_eq!(
+            parse_timeout_override("slow.sh:5m"),
+            Some((String::from("slow.sh"), Some(Duration::from_secs(300))))
+        );
+        assert_eq!(
+            parse_timeout_override("slow.sh:infinity"),
+            Some((String::from("slow.sh"), None))
+        );
+        assert_eq!(parse_timeout_override("slow.sh"), None);
+        assert_eq!(parse_timeout_override("slow.sh:soon"), None);
+        assert_eq!(parse_timeout_override(":60"), None);
     }
 
-    fn setup_folder_structure(passing: bool) -> Result<()> {
+    fn setup_folder_structure(install_paths: &[String], passing: bool) -> Result<()> {
         let passing_test_scripts = "testing_assets/passing_script.sh";
         let failing_test_scripts = "testing_assets/failing_script.sh";
         let passing_test_binary = "testing_assets/passing_binary";
         let failing_test_binary = "testing_assets/failing_binary";
 
-        for install_path in GREENBOOT_INSTALL_PATHS {
+        for install_path in install_paths {
             let required_path = format!("{install_path}/check/required.d");
             let wanted_path = format!("{install_path}/check/wanted.d");
             fs::create_dir_all(&required_path).expect("cannot create folder");
@@ -445,11 +3400,4 @@ mod test {
         }
         Ok(())
     }
-
-    fn tear_down() -> Result<()> {
-        for path in GREENBOOT_INSTALL_PATHS {
-            fs::remove_dir_all(path).expect("Unable to delete folder");
-        }
-        Ok(())
-    }
 }
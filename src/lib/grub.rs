@@ -1,170 +1,907 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
-use anyhow::{Context, Result, bail};
-use std::process::Command;
-use std::str;
+use anyhow::{Result, bail};
+use glob::{Pattern, glob};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::store::BootStateStore;
+use crate::sysroot::{rooted, sysroot};
+
+/// Well-known grubenv locations, tried in order: Fedora style, then Debian style
+const GRUBENV_CANDIDATES: [&str; 2] = ["/boot/grub2/grubenv", "/boot/grub/grubenv"];
 
 /// Shared GRUB environment path used by default helpers
-static GRUB_PATH: &str = "/boot/grub2/grubenv";
+const GRUB_PATH: &str = GRUBENV_CANDIDATES[0];
+
+/// directory holding the EFI vendor directories on the ESP, whose grubenv is
+/// used on EFI-only setups
+static EFI_DIR: &str = "/boot/efi/EFI";
 
-/// fetches boot_counter value, none if not set
-pub fn get_boot_counter() -> Result<Option<i32>> {
-    get_boot_counter_at(GRUB_PATH)
+/// symlinks followed when resolving a path, as in the kernel's MAXSYMLINKS
+const MAX_SYMLINKS: usize = 40;
+
+/// default grubenv, below the sysroot
+fn grub_path() -> String {
+    rooted(GRUB_PATH).to_string_lossy().into_owned()
 }
 
-fn get_boot_counter_at(grub_path: &str) -> Result<Option<i32>> {
-    let grub_vars = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("list")
-        .output()?;
-    let grub_vars = str::from_utf8(&grub_vars.stdout[..])?;
-    for var in grub_vars.lines() {
-        let (k, v) = if let Some(kv) = var.split_once('=') {
-            kv
-        } else {
-            continue;
+/// Size of a GRUB environment block, as created by grub2-editenv
+pub const GRUBENV_SIZE: usize = 1024;
+
+/// Signature every GRUB environment block has to start with
+const GRUBENV_HEADER: &str = "# GRUB Environment Block\n";
+
+#[derive(Debug, Error)]
+pub enum GrubEnvError {
+    #[error("Failed to read grubenv {0}: {1}")]
+    ReadFailed(String, std::io::Error),
+    #[error("Failed to write grubenv {0}: {1}")]
+    WriteFailed(String, std::io::Error),
+    #[error("grubenv {0} has size {1}, expected {GRUBENV_SIZE} bytes")]
+    InvalidSize(String, usize),
+    #[error("grubenv {0} does not start with a GRUB environment block header")]
+    InvalidHeader(String),
+    #[error("grubenv variables need {0} bytes, more than the {GRUBENV_SIZE} byte block")]
+    BlockFull(usize),
+    #[error("Invalid grubenv key: {0:?}")]
+    InvalidKey(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// a single line of the environment block below the header
+enum EnvLine {
+    /// `#` comment line, kept verbatim so rewrites don't drop it
+    Comment(String),
+    /// `key=value` assignment with the value unescaped
+    Var(String, String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// In-memory representation of a GRUB environment block (grubenv).
+///
+/// The on-disk format is a fixed size block starting with
+/// `# GRUB Environment Block`, followed by `key=value` lines and padded
+/// with `#` up to [`GRUBENV_SIZE`] bytes. Backslashes and newlines inside
+/// values are escaped with a backslash, just like grub2-editenv does.
+pub struct GrubEnv {
+    lines: Vec<EnvLine>,
+}
+
+impl GrubEnv {
+    /// creates an empty environment block
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// reads and parses the environment block stored at `path`
+    pub fn load(path: &Path) -> Result<Self, GrubEnvError> {
+        let display = path.display().to_string();
+        let path = resolve_rooted(path)
+            .map_err(|e| GrubEnvError::ReadFailed(display.clone(), io::Error::other(e)))?;
+        let block = fs::read(path).map_err(|e| GrubEnvError::ReadFailed(display.clone(), e))?;
+        Self::parse(&display, &block)
+    }
+
+    /// parses a raw environment block, `origin` is only used in error messages
+    pub fn parse(origin: &str, block: &[u8]) -> Result<Self, GrubEnvError> {
+        if block.len() != GRUBENV_SIZE {
+            return Err(GrubEnvError::InvalidSize(origin.to_string(), block.len()));
+        }
+        let body = block
+            .strip_prefix(GRUBENV_HEADER.as_bytes())
+            .ok_or_else(|| GrubEnvError::InvalidHeader(origin.to_string()))?;
+
+        let mut lines = Vec::new();
+        let mut pos = 0;
+        while pos < body.len() {
+            let rest = &body[pos..];
+            if rest[0] == b'#' {
+                // An unterminated comment is the padding at the end of the block
+                let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+                    break;
+                };
+                lines.push(EnvLine::Comment(
+                    String::from_utf8_lossy(&rest[..end]).into_owned(),
+                ));
+                pos += end + 1;
+                continue;
+            }
+
+            let (raw, end, terminated) = read_escaped_line(rest);
+            pos += end + 1;
+            if !terminated {
+                // grub only considers newline terminated assignments
+                break;
+            }
+
+            let line = String::from_utf8_lossy(&raw).into_owned();
+            match line.split_once('=') {
+                Some((key, value)) if !key.is_empty() => {
+                    lines.push(EnvLine::Var(key.to_string(), value.to_string()));
+                }
+                _ => log::warn!("Ignoring malformed grubenv line in {origin}: {line:?}"),
+            }
+        }
+
+        Ok(Self { lines })
+    }
+
+    /// returns the value of `key`, none if not set
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            EnvLine::Var(k, v) if k == key => Some(v.as_str()),
+            _ => None,
+        })
+    }
+
+    /// iterates over all variables in block order
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| match line {
+            EnvLine::Var(k, v) => Some((k.as_str(), v.as_str())),
+            EnvLine::Comment(_) => None,
+        })
+    }
+
+    /// sets `key` to `value`, replacing an existing assignment in place
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), GrubEnvError> {
+        if key.is_empty() || key.starts_with('#') || key.contains(['=', '\n', '\\']) {
+            return Err(GrubEnvError::InvalidKey(key.to_string()));
+        }
+        for line in self.lines.iter_mut() {
+            if let EnvLine::Var(k, v) = line
+                && k == key
+            {
+                *v = value.to_string();
+                return Ok(());
+            }
+        }
+        self.lines
+            .push(EnvLine::Var(key.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// removes `key`, returns true if it was set
+    pub fn unset(&mut self, key: &str) -> bool {
+        let before = self.lines.len();
+        self.lines
+            .retain(|line| !matches!(line, EnvLine::Var(k, _) if k == key));
+        before != self.lines.len()
+    }
+
+    /// serializes the environment into a padded block of [`GRUBENV_SIZE`] bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GrubEnvError> {
+        let mut block = Vec::with_capacity(GRUBENV_SIZE);
+        block.extend_from_slice(GRUBENV_HEADER.as_bytes());
+        for line in &self.lines {
+            match line {
+                EnvLine::Comment(c) => block.extend_from_slice(c.as_bytes()),
+                EnvLine::Var(k, v) => {
+                    block.extend_from_slice(k.as_bytes());
+                    block.push(b'=');
+                    for b in v.bytes() {
+                        if b == b'\\' || b == b'\n' {
+                            block.push(b'\\');
+                        }
+                        block.push(b);
+                    }
+                }
+            }
+            block.push(b'\n');
+        }
+        if block.len() > GRUBENV_SIZE {
+            return Err(GrubEnvError::BlockFull(block.len()));
+        }
+        block.resize(GRUBENV_SIZE, b'#');
+        Ok(block)
+    }
+
+    /// Atomically replaces the block at `path`.
+    ///
+    /// The new block is written to a temporary file next to the target,
+    /// synced and renamed over it, so a power cut leaves either the old
+    /// or the new block behind. Symlinks are resolved first, inside the
+    /// sysroot, so the link itself is kept.
+    pub fn save(&self, path: &Path) -> Result<(), GrubEnvError> {
+        let block = self.to_bytes()?;
+        // a link that cannot be resolved inside the sysroot is replaced
+        let target = resolve_rooted(path).unwrap_or_else(|_| path.to_path_buf());
+        let display = target.display().to_string();
+        let write_err = |e| GrubEnvError::WriteFailed(display.clone(), e);
+
+        let dir = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
         };
-        if k != "boot_counter" {
-            continue;
+        let mut tmp = tempfile::Builder::new()
+            .prefix(".grubenv.")
+            .tempfile_in(dir)
+            .map_err(write_err)?;
+        let permissions = match fs::metadata(&target) {
+            Ok(metadata) => metadata.permissions(),
+            Err(_) => std::os::unix::fs::PermissionsExt::from_mode(0o644),
+        };
+        tmp.as_file()
+            .set_permissions(permissions)
+            .map_err(write_err)?;
+        tmp.write_all(&block).map_err(write_err)?;
+        tmp.as_file().sync_all().map_err(write_err)?;
+        tmp.persist(&target).map_err(|e| write_err(e.error))?;
+        fs::File::open(dir)
+            .and_then(|d| d.sync_all())
+            .map_err(write_err)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Outcome of validating a grubenv block
+pub enum GrubEnvStatus {
+    /// block is well formed and greenboot's variables have sane values
+    Ok,
+    /// block is truncated or oversized, GRUB refuses to load it
+    WrongSize(usize),
+    /// block does not start with the GRUB environment block header
+    BadHeader,
+    /// block loads, but these variables hold values greenboot cannot use
+    GarbageValues(Vec<String>),
+}
+
+impl GrubEnvStatus {
+    /// true if GRUB and greenboot can both rely on the block
+    pub fn is_ok(&self) -> bool {
+        *self == GrubEnvStatus::Ok
+    }
+}
+
+impl fmt::Display for GrubEnvStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrubEnvStatus::Ok => write!(f, "ok"),
+            GrubEnvStatus::WrongSize(size) => {
+                write!(f, "wrong size ({size} bytes, expected {GRUBENV_SIZE})")
+            }
+            GrubEnvStatus::BadHeader => write!(f, "bad header"),
+            GrubEnvStatus::GarbageValues(keys) => {
+                write!(f, "garbage values in {}", keys.join(", "))
+            }
+        }
+    }
+}
+
+/// checks the value of a variable greenboot or GRUB's boot counting relies on,
+/// unknown variables are always accepted
+fn is_valid_value(key: &str, value: &str) -> bool {
+    match key {
+        "boot_counter" => value.parse::<i32>().is_ok(),
+        "boot_indeterminate" => value.parse::<u32>().is_ok(),
+        "boot_success" | "greenboot_rollback_trigger" => matches!(value, "0" | "1"),
+        _ => true,
+    }
+}
+
+/// Reads the assignment line at the start of `rest`, honouring backslash
+/// escapes in values. Returns the unescaped line, its length in `rest`
+/// without the newline, and whether a newline terminated it.
+fn read_escaped_line(rest: &[u8]) -> (Vec<u8>, usize, bool) {
+    let mut end = 0;
+    let mut raw = Vec::new();
+    while end < rest.len() {
+        match rest[end] {
+            b'\\' if end + 1 < rest.len() => {
+                raw.push(rest[end + 1]);
+                end += 2;
+            }
+            b'\n' => return (raw, end, true),
+            b => {
+                raw.push(b);
+                end += 1;
+            }
         }
+    }
+    (raw, end, false)
+}
 
-        return match v.parse::<i32>() {
-            Ok(n) => Ok(Some(n)),
-            Err(_) => Err(anyhow::anyhow!("boot_counter has invalid value: {}", v)),
+/// true for names grub2-editenv would create, letters, digits and underscores
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+impl GrubEnv {
+    /// classifies a raw environment block without failing on corruption
+    pub fn validate(block: &[u8]) -> GrubEnvStatus {
+        if block.len() != GRUBENV_SIZE {
+            return GrubEnvStatus::WrongSize(block.len());
+        }
+        let env = match Self::parse("", block) {
+            Ok(env) => env,
+            Err(_) => return GrubEnvStatus::BadHeader,
         };
+        let garbage: Vec<String> = env
+            .vars()
+            .filter(|(key, value)| !is_valid_value(key, value))
+            .map(|(key, _)| key.to_string())
+            .collect();
+        if garbage.is_empty() {
+            GrubEnvStatus::Ok
+        } else {
+            GrubEnvStatus::GarbageValues(garbage)
+        }
+    }
+
+    /// Rebuilds an environment from whatever is recognizable in a corrupt block.
+    ///
+    /// Complete `key=value` lines with a sane key and value are kept, comments,
+    /// a cut off last line and variables with garbage values are dropped and
+    /// returned as the second element.
+    pub fn salvage(block: &[u8]) -> (Self, Vec<String>) {
+        let body = block
+            .strip_prefix(GRUBENV_HEADER.as_bytes())
+            .unwrap_or(block);
+
+        let mut env = Self::new();
+        let mut dropped = Vec::new();
+        let mut pos = 0;
+        while pos < body.len() {
+            let rest = &body[pos..];
+            if rest[0] == b'#' {
+                // The padding, or a comment that is not kept
+                let end = rest.iter().position(|&b| b == b'\n');
+                if end.is_none() && !rest.iter().all(|&b| b == b'#') {
+                    dropped.push(String::from_utf8_lossy(rest).into_owned());
+                }
+                pos += end.map_or(rest.len(), |end| end + 1);
+                continue;
+            }
+
+            let (raw, end, terminated) = read_escaped_line(rest);
+            let line = String::from_utf8_lossy(&rest[..end]).into_owned();
+            pos += end + 1;
+            if !terminated {
+                // cut off by truncation
+                dropped.push(line);
+                break;
+            }
+            if raw.is_empty() {
+                continue;
+            }
+            let usable = String::from_utf8(raw).ok().and_then(|line| {
+                let (key, value) = line.split_once('=')?;
+                (is_valid_key(key)
+                    && !value.chars().any(|c| c.is_control() && c != '\n')
+                    && is_valid_value(key, value))
+                .then(|| (key.to_string(), value.to_string()))
+            });
+            match usable {
+                Some((key, value)) => {
+                    // Only fails on invalid keys, which were filtered above
+                    let _ = env.set(&key, &value);
+                }
+                None => dropped.push(line),
+            }
+        }
+
+        (env, dropped)
     }
-    Ok(None)
 }
 
-/// sets grub variable boot_counter if not set
-pub fn set_boot_counter(reboot_count: u16) -> Result<()> {
-    set_boot_counter_at(reboot_count, GRUB_PATH)
+/// reads the block at `path` and classifies it
+pub fn validate_grubenv(path: &Path) -> Result<GrubEnvStatus, GrubEnvError> {
+    let block =
+        fs::read(path).map_err(|e| GrubEnvError::ReadFailed(path.display().to_string(), e))?;
+    Ok(GrubEnv::validate(&block))
 }
 
-fn set_boot_counter_at(reboot_count: u16, grub_path: &str) -> Result<()> {
-    match get_boot_counter_at(grub_path) {
-        Ok(Some(i)) => {
-            bail!("already set boot_counter={i}");
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Result of [`repair_grubenv`]
+pub struct GrubEnvRepair {
+    /// classification of the block before the repair
+    pub status: GrubEnvStatus,
+    /// variables carried over into the rewritten block
+    pub kept: Vec<String>,
+    /// lines that could not be recovered
+    pub dropped: Vec<String>,
+}
+
+/// Rewrites a corrupt grubenv as a clean block.
+///
+/// Recognizable variables such as `saved_entry` are preserved, see
+/// [`GrubEnv::salvage`]. A block that validates fine is left untouched.
+pub fn repair_grubenv(path: &Path) -> Result<GrubEnvRepair> {
+    let block =
+        fs::read(path).map_err(|e| GrubEnvError::ReadFailed(path.display().to_string(), e))?;
+    let status = GrubEnv::validate(&block);
+    if status.is_ok() {
+        return Ok(GrubEnvRepair {
+            status,
+            kept: vec![],
+            dropped: vec![],
+        });
+    }
+
+    let (env, dropped) = GrubEnv::salvage(&block);
+    env.save(path)?;
+    Ok(GrubEnvRepair {
+        status,
+        kept: env.vars().map(|(key, _)| key.to_string()).collect(),
+        dropped,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// a single staged change of a [`BootState`] transaction
+pub enum BootStateOp {
+    /// set variable to value
+    Set(String, String),
+    /// remove variable
+    Unset(String),
+}
+
+impl BootStateOp {
+    /// variable the operation changes
+    pub fn key(&self) -> &str {
+        match self {
+            BootStateOp::Set(key, _) | BootStateOp::Unset(key) => key,
         }
-        Ok(None) => {
-            log::info!("boot_counter does not exists");
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Transaction of boot state changes committed with a single write.
+///
+/// Operations are applied in the order they were staged, so a power cut
+/// can never leave e.g. `boot_success=1` next to a stale `boot_counter`.
+///
+/// ```no_run
+/// use greenboot::BootState;
+///
+/// BootState::new()
+///     .set_boot_status(true)
+///     .unset_rollback_trigger()
+///     .commit()
+///     .unwrap();
+/// ```
+pub struct BootState {
+    ops: Vec<BootStateOp>,
+}
+
+impl BootState {
+    /// starts an empty transaction
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// stages `key=value`
+    pub fn set(mut self, key: &str, value: impl ToString) -> Self {
+        self.ops
+            .push(BootStateOp::Set(key.to_string(), value.to_string()));
+        self
+    }
+
+    /// stages removal of `key`
+    pub fn unset(mut self, key: &str) -> Self {
+        self.ops.push(BootStateOp::Unset(key.to_string()));
+        self
+    }
+
+    /// stages boot_success, a successful boot also clears boot_counter
+    pub fn set_boot_status(self, success: bool) -> Self {
+        let state = self.set("boot_success", u8::from(success));
+        if success {
+            return state.unset_boot_counter();
         }
-        Err(_) => {
-            // Counter exists but has invalid value - overwrite it
-            log::warn!("boot_counter exists with invalid value - overwriting");
+        state
+    }
+
+    /// stages boot_counter
+    pub fn set_boot_counter(self, reboot_count: u16) -> Self {
+        self.set("boot_counter", reboot_count)
+    }
+
+    /// stages removal of boot_counter
+    pub fn unset_boot_counter(self) -> Self {
+        self.unset("boot_counter")
+    }
+
+    /// stages greenboot_rollback_trigger=1
+    pub fn set_rollback_trigger(self) -> Self {
+        self.set("greenboot_rollback_trigger", 1)
+    }
+
+    /// stages removal of greenboot_rollback_trigger
+    pub fn unset_rollback_trigger(self) -> Self {
+        self.unset("greenboot_rollback_trigger")
+    }
+
+    /// stages boot_indeterminate=0, a good boot settles one GRUB recorded as
+    /// indeterminate like grub2-set-bootflag does. Other bootloaders have no
+    /// such state and ignore it.
+    pub fn settle_indeterminate(self) -> Self {
+        self.set("boot_indeterminate", 0)
+    }
+
+    /// staged operations in commit order
+    pub fn ops(&self) -> &[BootStateOp] {
+        &self.ops
+    }
+
+    /// true if nothing has been staged
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// applies the staged operations to `grubenv` without writing it
+    pub fn apply(&self, grubenv: &mut GrubEnv) -> Result<(), GrubEnvError> {
+        for op in &self.ops {
+            match op {
+                BootStateOp::Set(key, value) => grubenv.set(key, value)?,
+                BootStateOp::Unset(key) => {
+                    grubenv.unset(key);
+                }
+            }
         }
+        Ok(())
     }
 
-    log::info!("setting boot counter");
-    set_grub_var("boot_counter", reboot_count, grub_path)?;
-    Ok(())
+    /// writes all staged operations to the default grubenv in one atomic write
+    pub fn commit(self) -> Result<()> {
+        self.commit_at(&grub_path())
+    }
+
+    fn commit_at(self, grub_path: &str) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let path = Path::new(grub_path);
+        let mut grubenv = GrubEnv::load(path)?;
+        self.apply(&mut grubenv)?;
+        grubenv.save(path)?;
+
+        for op in &self.ops {
+            match op {
+                BootStateOp::Set(key, value) => log::info!("Set grubenv: {key}={value}"),
+                BootStateOp::Unset(key) => log::info!("Clear grubenv: {key}"),
+            }
+        }
+        Ok(())
+    }
 }
-/// sets grub variable boot_success
-pub fn set_boot_status(success: bool) -> Result<()> {
-    set_boot_status_at(success, GRUB_PATH)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where the bootloader's boot counting stands, as seen by the booted system
+pub enum BootCountState {
+    /// no boot counting in progress
+    Normal,
+    /// boot attempts left before the bootloader falls back
+    CountingDown(u16),
+    /// countdown ended, the next boot falls back to the previous entry
+    Exhausted,
+    /// the bootloader already booted the fallback entry, 08_greenboot.cfg
+    /// marks this with `boot_counter=-1`
+    FellBack,
+    /// no counting in progress, but GRUB recorded `boot_indeterminate`
+    /// boots that neither succeeded nor failed
+    Indeterminate(u32),
 }
 
-fn set_boot_status_at(success: bool, grub_path: &str) -> Result<()> {
-    if success {
-        set_grub_var("boot_success", 1, grub_path)?;
-        unset_boot_counter_at(grub_path)?;
-        return Ok(());
+impl BootCountState {
+    /// state for a boot_counter value
+    pub fn from_counter(boot_counter: Option<i32>) -> Self {
+        match boot_counter {
+            None => BootCountState::Normal,
+            Some(counter) if counter > 0 => {
+                BootCountState::CountingDown(u16::try_from(counter).unwrap_or(u16::MAX))
+            }
+            Some(0) => BootCountState::Exhausted,
+            Some(_) => BootCountState::FellBack,
+        }
+    }
+
+    /// state of a grubenv, taking boot_indeterminate into account
+    pub fn from_env(grubenv: &GrubEnv) -> Result<Self> {
+        let boot_counter = match grubenv.get("boot_counter") {
+            Some(v) => Some(
+                v.parse::<i32>()
+                    .map_err(|_| anyhow::anyhow!("boot_counter has invalid value: {v}"))?,
+            ),
+            None => None,
+        };
+        let indeterminate = grubenv
+            .get("boot_indeterminate")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(0);
+        match Self::from_counter(boot_counter) {
+            BootCountState::Normal if indeterminate > 0 => {
+                Ok(BootCountState::Indeterminate(indeterminate))
+            }
+            state => Ok(state),
+        }
     }
-    set_grub_var("boot_success", 0, grub_path)
 }
 
-/// unset boot_counter
-pub fn unset_boot_counter() -> Result<()> {
-    unset_boot_counter_at(GRUB_PATH)
+impl fmt::Display for BootCountState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootCountState::Normal => write!(f, "normal"),
+            BootCountState::CountingDown(left) => write!(f, "counting down, {left} left"),
+            BootCountState::Exhausted => write!(f, "exhausted"),
+            BootCountState::FellBack => write!(f, "fell back"),
+            BootCountState::Indeterminate(n) => write!(f, "indeterminate ({n})"),
+        }
+    }
 }
 
-fn unset_boot_counter_at(grub_path: &str) -> Result<()> {
-    unset_grub_var("boot_counter", grub_path)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// how the grubenv in use was found
+pub enum GrubenvSource {
+    /// GREENBOOT_GRUBENV_PATH from the config file
+    Config,
+    /// one of the well-known /boot locations
+    Boot,
+    /// an EFI vendor directory on the ESP
+    Efi,
 }
 
-/// sets greenboot_rollback_trigger=1
-pub fn set_rollback_trigger() -> Result<()> {
-    set_rollback_trigger_at(GRUB_PATH)
+impl fmt::Display for GrubenvSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrubenvSource::Config => write!(f, "config override"),
+            GrubenvSource::Boot => write!(f, "/boot"),
+            GrubenvSource::Efi => write!(f, "EFI system partition"),
+        }
+    }
 }
 
-fn set_rollback_trigger_at(grub_path: &str) -> Result<()> {
-    set_grub_var("greenboot_rollback_trigger", 1, grub_path)
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// grubenv found by [`discover_grubenv`]
+pub struct GrubenvLocation {
+    /// path as configured or found
+    pub path: PathBuf,
+    /// path with symlinks resolved, the file that actually gets written
+    pub resolved: PathBuf,
+    /// how the grubenv was found
+    pub source: GrubenvSource,
 }
 
-/// unsets greenboot_rollback_trigger
-pub fn unset_rollback_trigger() -> Result<()> {
-    unset_rollback_trigger_at(GRUB_PATH)
+/// Finds the grubenv in use.
+///
+/// A configured `override_path` always wins and has to exist. Otherwise the
+/// well-known /boot locations are tried before EFI vendor directories, and
+/// symlinks (e.g. /boot/grub2/grubenv pointing into /boot/efi/EFI/fedora)
+/// are resolved so the caller knows which filesystem holds the block.
+pub fn discover_grubenv(override_path: Option<&Path>) -> Result<GrubenvLocation> {
+    discover_grubenv_at(sysroot(), override_path)
 }
 
-fn unset_rollback_trigger_at(grub_path: &str) -> Result<()> {
-    unset_grub_var("greenboot_rollback_trigger", grub_path)
+fn discover_grubenv_at(root: &Path, override_path: Option<&Path>) -> Result<GrubenvLocation> {
+    let locate = |path: &Path, source| -> Result<GrubenvLocation> {
+        let resolved = resolve_in_root(root, path)?;
+        if !resolved.is_file() {
+            bail!("{} is not a file", resolved.display());
+        }
+        Ok(GrubenvLocation {
+            path: root.join(path.strip_prefix("/").unwrap_or(path)),
+            resolved,
+            source,
+        })
+    };
+
+    if let Some(path) = override_path {
+        return locate(path, GrubenvSource::Config).map_err(|e| {
+            anyhow::anyhow!("configured grubenv {} is not usable: {e}", path.display())
+        });
+    }
+
+    for candidate in GRUBENV_CANDIDATES {
+        if let Ok(location) = locate(Path::new(candidate), GrubenvSource::Boot) {
+            return Ok(location);
+        }
+    }
+
+    // the ESP itself may be reached through a link, so glob below where it
+    // resolves to inside the root
+    let mut efi_candidates: Vec<GrubenvLocation> = vec![];
+    if let Ok(efi_dir) = resolve_in_root(root, Path::new(EFI_DIR)) {
+        let pattern = format!("{}/*/grubenv", Pattern::escape(&efi_dir.to_string_lossy()));
+        let mut vendors: Vec<PathBuf> = glob(&pattern)?
+            .filter_map(Result::ok)
+            .filter_map(|p| p.strip_prefix(&efi_dir).map(Path::to_path_buf).ok())
+            .collect();
+        vendors.sort();
+        efi_candidates = vendors
+            .iter()
+            .filter_map(|vendor| locate(&Path::new(EFI_DIR).join(vendor), GrubenvSource::Efi).ok())
+            .collect();
+    }
+    if efi_candidates.len() > 1 {
+        let paths: Vec<&Path> = efi_candidates.iter().map(|l| l.path.as_path()).collect();
+        log::warn!("Multiple grubenv found on the ESP: {paths:?}, using the first one");
+    }
+    if let Some(location) = efi_candidates.into_iter().next() {
+        return Ok(location);
+    }
+
+    bail!("cannot find grubenv in {GRUBENV_CANDIDATES:?} or {EFI_DIR}/*/grubenv")
 }
 
-/// gets greenboot_rollback_trigger value, returns true if set to 1
-pub fn get_rollback_trigger() -> Result<bool> {
-    get_rollback_trigger_at(GRUB_PATH)
+/// `path` below the sysroot with its symlinks resolved inside the sysroot,
+/// so an absolute link in an image never leads to the running system
+fn resolve_rooted(path: &Path) -> Result<PathBuf> {
+    resolve_rooted_in(sysroot(), path)
 }
 
-fn get_rollback_trigger_at(grub_path: &str) -> Result<bool> {
-    let grub_vars = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("list")
-        .output()
-        .context("Unable to list grubenv variables")?;
-
-    let output = String::from_utf8_lossy(&grub_vars.stdout);
-    for line in output.lines() {
-        if line.starts_with("greenboot_rollback_trigger=") {
-            let value = line.split('=').nth(1).unwrap_or("0");
-            return Ok(value == "1");
+fn resolve_rooted_in(root: &Path, path: &Path) -> Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    match path.strip_prefix(root) {
+        Ok(inner) => resolve_in_root(root, &Path::new("/").join(inner)),
+        Err(_) => bail!("{} is outside of {}", path.display(), root.display()),
+    }
+}
+
+/// Resolves the absolute `path` of the system at `root` the way the kernel
+/// would after a chroot into it: symlinks are followed one at a time and
+/// absolute link targets start over at `root`, never at the host's `/`.
+fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf> {
+    let root = root.canonicalize()?;
+    let mut resolved = root.clone();
+    // components still to walk, the next one last
+    let mut pending: Vec<OsString> = path
+        .components()
+        .rev()
+        .map(|c| c.as_os_str().to_owned())
+        .collect();
+    let mut links = 0;
+    while let Some(part) = pending.pop() {
+        match part.to_str() {
+            Some("/") => resolved = root.clone(),
+            Some(".") => {}
+            Some("..") => {
+                if resolved == root {
+                    bail!("{} leaves {}", path.display(), root.display());
+                }
+                resolved.pop();
+            }
+            _ => {
+                let next = resolved.join(&part);
+                let meta = fs::symlink_metadata(&next)
+                    .map_err(|e| anyhow::anyhow!("cannot access {}: {e}", next.display()))?;
+                if !meta.file_type().is_symlink() {
+                    resolved = next;
+                    continue;
+                }
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    bail!("too many levels of symlinks in {}", path.display());
+                }
+                let target = fs::read_link(&next)?;
+                pending.extend(target.components().rev().map(|c| c.as_os_str().to_owned()));
+            }
         }
     }
-    Ok(false) // Not set means false
+    if !resolved.starts_with(&root) {
+        bail!("{} resolves outside of {}", path.display(), root.display());
+    }
+    Ok(resolved)
 }
 
-fn unset_grub_var(key: &str, grub_path: &str) -> Result<()> {
-    // Execute GRUB command and capture result
-    let grub_result = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("unset")
-        .arg(key)
-        .status()
-        .context("Unable to clear boot_counter")?;
+#[derive(Debug, Clone)]
+/// [`BootStateStore`] keeping the boot state in a GRUB environment block
+pub struct Grub {
+    path: String,
+}
 
-    if !grub_result.success() {
-        bail!("Failed to unset grubenv key: {key}");
+impl Default for Grub {
+    fn default() -> Self {
+        Self::new(&grub_path())
+    }
+}
+
+impl Grub {
+    /// store backed by the grubenv at `path`
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
     }
 
-    log::info!("Clear grubenv: {key}");
-    Ok(())
+    /// store backed by the discovered grubenv
+    pub fn discover(override_path: Option<&Path>) -> Result<Self> {
+        let location = discover_grubenv(override_path)?;
+        if location.path == location.resolved {
+            log::info!(
+                "Using grubenv {} ({})",
+                location.path.display(),
+                location.source
+            );
+        } else {
+            log::info!(
+                "Using grubenv {} -> {} ({})",
+                location.path.display(),
+                location.resolved.display(),
+                location.source
+            );
+        }
+        Ok(Self::new(&location.resolved.to_string_lossy()))
+    }
+
+    /// grubenv this store reads and writes
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 }
 
-fn set_grub_var(key: &str, val: u16, grub_path: &str) -> Result<()> {
-    // Execute GRUB command and capture result
-    let grub_result = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("set")
-        .arg(format!("{key}={val}"))
-        .status()
-        .context("Unable to set grubenv")?;
+impl BootStateStore for Grub {
+    fn name(&self) -> &'static str {
+        "grub"
+    }
+
+    fn get_boot_counter(&self) -> Result<Option<i32>> {
+        get_boot_counter_at(&self.path)
+    }
+
+    fn get_rollback_trigger(&self) -> Result<bool> {
+        get_rollback_trigger_at(&self.path)
+    }
 
-    if !grub_result.success() {
-        bail!("Failed to set grubenv key: {key}");
+    fn get_boot_success(&self) -> Result<Option<bool>> {
+        let grubenv = GrubEnv::load(Path::new(&self.path))?;
+        Ok(grubenv.get("boot_success").map(|v| v == "1"))
+    }
+
+    fn get_boot_count_state(&self) -> Result<BootCountState> {
+        BootCountState::from_env(&GrubEnv::load(Path::new(&self.path))?)
+    }
+
+    fn commit(&self, state: BootState) -> Result<()> {
+        state.commit_at(&self.path)
+    }
+
+    fn storage_path(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.path))
+    }
+
+    fn verify(&self) -> Result<()> {
+        let status = validate_grubenv(Path::new(&self.path))?;
+        if !status.is_ok() {
+            bail!(
+                "grubenv {} is corrupt: {status}, run `greenboot repair-grubenv`",
+                self.path
+            );
+        }
+        Ok(())
+    }
+}
+
+fn get_boot_counter_at(grub_path: &str) -> Result<Option<i32>> {
+    let grubenv = GrubEnv::load(Path::new(grub_path))?;
+    match grubenv.get("boot_counter") {
+        Some(v) => match v.parse::<i32>() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(anyhow::anyhow!("boot_counter has invalid value: {}", v)),
+        },
+        None => Ok(None),
     }
+}
 
-    log::info!("Set grubenv: {key}={val}");
-    Ok(())
+fn get_rollback_trigger_at(grub_path: &str) -> Result<bool> {
+    let grubenv = GrubEnv::load(Path::new(grub_path))?;
+    // Not set means false
+    Ok(grubenv.get("greenboot_rollback_trigger") == Some("1"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        get_boot_counter_at, get_rollback_trigger_at, set_boot_counter_at, set_rollback_trigger_at,
-        unset_boot_counter_at, unset_rollback_trigger_at,
+        BootCountState, BootState, GRUBENV_HEADER, GRUBENV_SIZE, Grub, GrubEnv, GrubEnvError,
+        GrubEnvStatus, GrubenvSource, discover_grubenv_at, repair_grubenv, resolve_rooted_in,
     };
-    use anyhow::Context;
+    use crate::BootStateStore;
     use std::fs;
-    use std::process::Command;
+    use std::path::Path;
     use tempfile::TempDir;
     use tempfile::tempdir;
 
@@ -175,62 +912,207 @@ mod tests {
         (temp_dir, temp_grubenv.to_str().unwrap().to_string())
     }
 
+    fn set_raw_var(grubenv: &str, key: &str, value: &str) {
+        let mut env = GrubEnv::load(Path::new(grubenv)).unwrap();
+        env.set(key, value).unwrap();
+        env.save(Path::new(grubenv)).unwrap();
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let env = GrubEnv::load(Path::new("testing_assets/grubenv")).unwrap();
+        assert_eq!(
+            env.get("saved_entry"),
+            Some("72c9a2f70064454f91ec38fb40a84d77-6.8.7-200.fc39.x86_64")
+        );
+        assert_eq!(env.get("boot_success"), Some("1"));
+        assert_eq!(env.get("boot_indeterminate"), Some("2"));
+        assert_eq!(env.get("boot_counter"), None);
+        assert_eq!(env.vars().count(), 3);
+    }
+
+    #[test]
+    fn test_unmodified_block_round_trips() {
+        let original = fs::read("testing_assets/grubenv").unwrap();
+        let env = GrubEnv::parse("fixture", &original).unwrap();
+        assert_eq!(env.to_bytes().unwrap(), original);
+    }
+
+    #[test]
+    fn test_save_is_padded_and_keeps_other_vars() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        set_raw_var(&grubenv, "boot_counter", "2");
+
+        let block = fs::read(&grubenv).unwrap();
+        assert_eq!(block.len(), GRUBENV_SIZE);
+        let needle = b"boot_indeterminate=2\nboot_counter=2\n#";
+        assert!(block.windows(needle.len()).any(|w| w == needle));
+        let env = GrubEnv::parse("test", &block).unwrap();
+        assert_eq!(env.get("boot_indeterminate"), Some("2"));
+        assert!(env.get("saved_entry").is_some());
+    }
+
+    #[test]
+    fn test_value_escaping() {
+        let mut env = GrubEnv::new();
+        env.set("weird", "a\\b\nc").unwrap();
+        let block = env.to_bytes().unwrap();
+        let parsed = GrubEnv::parse("test", &block).unwrap();
+        assert_eq!(parsed.get("weird"), Some("a\\b\nc"));
+    }
+
+    #[test]
+    fn test_invalid_blocks() {
+        let truncated = vec![b'#'; 512];
+        assert!(matches!(
+            GrubEnv::parse("test", &truncated),
+            Err(GrubEnvError::InvalidSize(_, 512))
+        ));
+
+        let headerless = vec![b'#'; GRUBENV_SIZE];
+        assert!(matches!(
+            GrubEnv::parse("test", &headerless),
+            Err(GrubEnvError::InvalidHeader(_))
+        ));
+
+        assert!(matches!(
+            GrubEnv::load(Path::new("testing_assets/does_not_exist")),
+            Err(GrubEnvError::ReadFailed(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_validate_classifies_blocks() {
+        let fixture = fs::read("testing_assets/grubenv").unwrap();
+        assert_eq!(GrubEnv::validate(&fixture), GrubEnvStatus::Ok);
+        assert_eq!(
+            GrubEnv::validate(&fixture[..300]),
+            GrubEnvStatus::WrongSize(300)
+        );
+        assert_eq!(
+            GrubEnv::validate(&vec![b'#'; GRUBENV_SIZE]),
+            GrubEnvStatus::BadHeader
+        );
+
+        let mut env = GrubEnv::parse("test", &fixture).unwrap();
+        env.set("boot_counter", "foo").unwrap();
+        env.set("greenboot_rollback_trigger", "yes").unwrap();
+        assert_eq!(
+            GrubEnv::validate(&env.to_bytes().unwrap()),
+            GrubEnvStatus::GarbageValues(vec![
+                "boot_counter".to_string(),
+                "greenboot_rollback_trigger".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_salvage_truncated_block() {
+        let block = format!(
+            "{GRUBENV_HEADER}saved_entry=fedora-6.8\nboot_counter=x\n\x01bad=1\nboot_success=1\nkernel"
+        );
+        let (env, dropped) = GrubEnv::salvage(block.as_bytes());
+        assert_eq!(
+            env.vars().collect::<Vec<_>>(),
+            vec![("saved_entry", "fedora-6.8"), ("boot_success", "1")]
+        );
+        assert_eq!(dropped, vec!["boot_counter=x", "\x01bad=1", "kernel"]);
+
+        // Escaped backslashes and newlines are unescaped as the parser does
+        let block = format!("{GRUBENV_HEADER}cmdline=a\\\nb\\\\c\nboot_counter=");
+        let (env, dropped) = GrubEnv::salvage(block.as_bytes());
+        assert_eq!(env.get("cmdline"), Some("a\nb\\c"));
+        assert_eq!(dropped, vec!["boot_counter="]);
+
+        // Without a header the first line is salvaged as well
+        let (env, _) = GrubEnv::salvage(b"saved_entry=0\n######");
+        assert_eq!(env.get("saved_entry"), Some("0"));
+    }
+
+    #[test]
+    fn test_repair_grubenv() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        let path = Path::new(&grubenv);
+        let repair = repair_grubenv(path).unwrap();
+        assert!(repair.status.is_ok());
+        assert_eq!(
+            fs::read(path).unwrap(),
+            fs::read("testing_assets/grubenv").unwrap()
+        );
+
+        let fixture = fs::read(path).unwrap();
+        fs::write(path, &fixture[..200]).unwrap();
+        let repair = repair_grubenv(path).unwrap();
+        assert_eq!(repair.status, GrubEnvStatus::WrongSize(200));
+        assert!(repair.kept.contains(&"saved_entry".to_string()));
+        assert!(GrubEnv::validate(&fs::read(path).unwrap()).is_ok());
+        let env = GrubEnv::load(path).unwrap();
+        assert_eq!(
+            env.get("saved_entry"),
+            Some("72c9a2f70064454f91ec38fb40a84d77-6.8.7-200.fc39.x86_64")
+        );
+    }
+
+    #[test]
+    fn test_block_full() {
+        let mut env = GrubEnv::new();
+        env.set("filler", &"x".repeat(GRUBENV_SIZE)).unwrap();
+        assert!(matches!(env.to_bytes(), Err(GrubEnvError::BlockFull(_))));
+        assert!(env.set("bad=key", "1").is_err());
+    }
+
+    #[test]
+    fn test_save_follows_symlink() {
+        let (temp_dir, grubenv) = setup_test_paths();
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&grubenv, &link).unwrap();
+
+        set_raw_var(link.to_str().unwrap(), "boot_counter", "1");
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(1));
+    }
+
     #[test]
     fn test_boot_counter_set() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        set_boot_counter_at(10, &grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(10));
+        Grub::new(&grubenv).set_boot_counter(10).unwrap();
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(10));
     }
 
     #[test]
     fn test_boot_counter_re_set() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=99")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        set_boot_counter_at(20, &grubenv).ok();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(99));
+        set_raw_var(&grubenv, "boot_counter", "99");
+        Grub::new(&grubenv).set_boot_counter(20).ok();
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(99));
     }
 
     #[test]
     fn test_boot_counter_having_invalid_value() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=foo")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        set_boot_counter_at(13, &grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(13));
+        set_raw_var(&grubenv, "boot_counter", "foo");
+        Grub::new(&grubenv).set_boot_counter(13).unwrap();
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(13));
     }
 
     #[test]
     fn test_unset_boot_counter() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=199")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        unset_boot_counter_at(&grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), None);
+        set_raw_var(&grubenv, "boot_counter", "199");
+        Grub::new(&grubenv).unset_boot_counter().unwrap();
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), None);
     }
 
     #[test]
     fn test_get_boot_counter() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=99")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(99));
+        set_raw_var(&grubenv, "boot_counter", "99");
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(99));
     }
 
     #[test]
@@ -238,15 +1120,15 @@ mod tests {
         let (_temp_dir, grubenv) = setup_test_paths();
 
         // Test when rollback trigger is not set
-        assert!(!get_rollback_trigger_at(&grubenv).unwrap());
+        assert!(!Grub::new(&grubenv).get_rollback_trigger().unwrap());
 
         // Test setting rollback trigger
-        set_rollback_trigger_at(&grubenv).unwrap();
-        assert!(get_rollback_trigger_at(&grubenv).unwrap());
+        Grub::new(&grubenv).set_rollback_trigger().unwrap();
+        assert!(Grub::new(&grubenv).get_rollback_trigger().unwrap());
 
         // Test unsetting rollback trigger
-        unset_rollback_trigger_at(&grubenv).unwrap();
-        assert!(!get_rollback_trigger_at(&grubenv).unwrap());
+        Grub::new(&grubenv).unset_rollback_trigger().unwrap();
+        assert!(!Grub::new(&grubenv).get_rollback_trigger().unwrap());
     }
 
     #[test]
@@ -254,18 +1136,236 @@ mod tests {
         let (_temp_dir, grubenv) = setup_test_paths();
 
         // Set boot counter
-        set_boot_counter_at(3, &grubenv).unwrap();
+        Grub::new(&grubenv).set_boot_counter(3).unwrap();
 
         // Set rollback trigger
-        set_rollback_trigger_at(&grubenv).unwrap();
+        Grub::new(&grubenv).set_rollback_trigger().unwrap();
 
         // Both should coexist
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(3));
-        assert!(get_rollback_trigger_at(&grubenv).unwrap());
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(3));
+        assert!(Grub::new(&grubenv).get_rollback_trigger().unwrap());
 
         // Unset rollback trigger, boot_counter should remain
-        unset_rollback_trigger_at(&grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(3));
-        assert!(!get_rollback_trigger_at(&grubenv).unwrap());
+        Grub::new(&grubenv).unset_rollback_trigger().unwrap();
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(3));
+        assert!(!Grub::new(&grubenv).get_rollback_trigger().unwrap());
+    }
+
+    #[test]
+    fn test_boot_state_commits_all_ops() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        set_raw_var(&grubenv, "boot_counter", "2");
+        Grub::new(&grubenv).set_rollback_trigger().unwrap();
+
+        BootState::new()
+            .set_boot_status(true)
+            .unset_rollback_trigger()
+            .commit_at(&grubenv)
+            .unwrap();
+
+        let env = GrubEnv::load(Path::new(&grubenv)).unwrap();
+        assert_eq!(env.get("boot_success"), Some("1"));
+        assert_eq!(env.get("boot_counter"), None);
+        assert_eq!(env.get("greenboot_rollback_trigger"), None);
+        assert!(env.get("saved_entry").is_some());
+    }
+
+    #[test]
+    fn test_boot_state_ops_apply_in_order() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+
+        let state = BootState::new()
+            .set_boot_status(false)
+            .set_boot_counter(3)
+            .unset_boot_counter()
+            .set_boot_counter(5);
+        assert_eq!(state.ops().len(), 4);
+        state.commit_at(&grubenv).unwrap();
+
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), Some(5));
+        let env = GrubEnv::load(Path::new(&grubenv)).unwrap();
+        assert_eq!(env.get("boot_success"), Some("0"));
+    }
+
+    #[test]
+    fn test_boot_state_failed_commit_writes_nothing() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        let original = fs::read(&grubenv).unwrap();
+
+        let result = BootState::new()
+            .set_boot_counter(3)
+            .set("filler", "x".repeat(GRUBENV_SIZE))
+            .commit_at(&grubenv);
+        assert!(result.is_err());
+        assert_eq!(fs::read(&grubenv).unwrap(), original);
+    }
+
+    #[test]
+    fn test_set_boot_status_success_clears_counter() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        Grub::new(&grubenv).set_boot_counter(2).unwrap();
+        Grub::new(&grubenv).set_boot_status(true).unwrap();
+        assert_eq!(Grub::new(&grubenv).get_boot_counter().unwrap(), None);
+    }
+
+    #[test]
+    fn test_boot_count_state() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        let state = || BootCountState::from_env(&GrubEnv::load(Path::new(&grubenv)).unwrap());
+        // The fixture carries boot_indeterminate=2 without a counter
+        assert_eq!(state().unwrap(), BootCountState::Indeterminate(2));
+
+        for (counter, expected) in [
+            ("3", BootCountState::CountingDown(3)),
+            ("0", BootCountState::Exhausted),
+            ("-1", BootCountState::FellBack),
+        ] {
+            set_raw_var(&grubenv, "boot_counter", counter);
+            assert_eq!(state().unwrap(), expected);
+        }
+
+        set_raw_var(&grubenv, "boot_counter", "foo");
+        assert!(state().is_err());
+    }
+
+    #[test]
+    fn test_good_boot_settles_indeterminate() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        Grub::new(&grubenv).set_boot_status(false).unwrap();
+        let env = GrubEnv::load(Path::new(&grubenv)).unwrap();
+        assert_eq!(env.get("boot_indeterminate"), Some("2"));
+
+        // only an explicit settle touches it, commits apply just the staged ops
+        Grub::new(&grubenv).set_boot_status(true).unwrap();
+        let env = GrubEnv::load(Path::new(&grubenv)).unwrap();
+        assert_eq!(env.get("boot_indeterminate"), Some("2"));
+
+        BootState::new()
+            .set_boot_status(true)
+            .settle_indeterminate()
+            .commit_at(&grubenv)
+            .unwrap();
+        let env = GrubEnv::load(Path::new(&grubenv)).unwrap();
+        assert_eq!(env.get("boot_indeterminate"), Some("0"));
+        assert_eq!(
+            BootCountState::from_env(&env).unwrap(),
+            BootCountState::Normal
+        );
+    }
+
+    #[test]
+    fn test_discover_grubenv_candidates() {
+        let root = tempdir().unwrap();
+        assert!(discover_grubenv_at(root.path(), None).is_err());
+
+        // EFI-only setups keep grubenv in the vendor directory
+        let efi = root.path().join("boot/efi/EFI/fedora");
+        fs::create_dir_all(&efi).unwrap();
+        fs::copy("testing_assets/grubenv", efi.join("grubenv")).unwrap();
+        let location = discover_grubenv_at(root.path(), None).unwrap();
+        assert_eq!(location.source, GrubenvSource::Efi);
+
+        // Debian style layouts are found on /boot
+        let debian = root.path().join("boot/grub");
+        fs::create_dir_all(&debian).unwrap();
+        fs::copy("testing_assets/grubenv", debian.join("grubenv")).unwrap();
+        let location = discover_grubenv_at(root.path(), None).unwrap();
+        assert_eq!(location.source, GrubenvSource::Boot);
+        assert_eq!(location.path, debian.join("grubenv"));
+    }
+
+    #[test]
+    fn test_discover_grubenv_resolves_symlink() {
+        let root = tempdir().unwrap();
+        let efi = root.path().join("boot/efi/EFI/fedora");
+        fs::create_dir_all(&efi).unwrap();
+        fs::create_dir_all(root.path().join("boot/grub2")).unwrap();
+        fs::copy("testing_assets/grubenv", efi.join("grubenv")).unwrap();
+        std::os::unix::fs::symlink(
+            "../efi/EFI/fedora/grubenv",
+            root.path().join("boot/grub2/grubenv"),
+        )
+        .unwrap();
+
+        let location = discover_grubenv_at(root.path(), None).unwrap();
+        assert_eq!(location.source, GrubenvSource::Boot);
+        assert_eq!(location.path, root.path().join("boot/grub2/grubenv"));
+        assert_eq!(
+            location.resolved,
+            fs::canonicalize(efi.join("grubenv")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_discover_grubenv_override() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("boot/grub2")).unwrap();
+        fs::copy(
+            "testing_assets/grubenv",
+            root.path().join("boot/grub2/grubenv"),
+        )
+        .unwrap();
+        fs::copy("testing_assets/grubenv", root.path().join("custom")).unwrap();
+
+        let location = discover_grubenv_at(root.path(), Some(Path::new("/custom"))).unwrap();
+        assert_eq!(location.source, GrubenvSource::Config);
+        assert_eq!(location.path, root.path().join("custom"));
+
+        // a missing override is an error instead of a silent fallback
+        assert!(discover_grubenv_at(root.path(), Some(Path::new("/missing"))).is_err());
+    }
+
+    #[test]
+    fn test_resolve_rooted_in() {
+        let root = tempdir().unwrap();
+        let root_path = fs::canonicalize(root.path()).unwrap();
+        let outside = tempdir().unwrap();
+        let host_grubenv = fs::canonicalize(outside.path()).unwrap().join("grubenv");
+        fs::copy("testing_assets/grubenv", &host_grubenv).unwrap();
+        fs::create_dir_all(root_path.join("boot/grub2")).unwrap();
+        let link = root_path.join("boot/grub2/grubenv");
+        // the absolute target names a file that exists on the host only
+        std::os::unix::fs::symlink(&host_grubenv, &link).unwrap();
+
+        assert!(resolve_rooted_in(&root_path, &link).is_err());
+        let inside = root_path.join(host_grubenv.strip_prefix("/").unwrap());
+        fs::create_dir_all(inside.parent().unwrap()).unwrap();
+        fs::copy("testing_assets/grubenv", &inside).unwrap();
+        assert_eq!(resolve_rooted_in(&root_path, &link).unwrap(), inside);
+        assert!(resolve_rooted_in(&root_path, outside.path()).is_err());
+    }
+
+    #[test]
+    fn test_discover_grubenv_absolute_symlink_stays_in_root() {
+        let root = tempdir().unwrap();
+        let root_path = fs::canonicalize(root.path()).unwrap();
+        let efi = root_path.join("boot/efi/EFI/fedora");
+        fs::create_dir_all(&efi).unwrap();
+        fs::create_dir_all(root_path.join("boot/grub2")).unwrap();
+        fs::copy("testing_assets/grubenv", efi.join("grubenv")).unwrap();
+        // Fedora links /boot/grub2/grubenv to the ESP with an absolute target,
+        // which has to point into the image, not at the running host
+        std::os::unix::fs::symlink(
+            "/boot/efi/EFI/fedora/grubenv",
+            root_path.join("boot/grub2/grubenv"),
+        )
+        .unwrap();
+
+        let location = discover_grubenv_at(&root_path, None).unwrap();
+        assert_eq!(location.source, GrubenvSource::Boot);
+        assert_eq!(location.resolved, efi.join("grubenv"));
+
+        // a link whose target is missing in the root is not a grubenv
+        fs::remove_file(efi.join("grubenv")).unwrap();
+        assert!(discover_grubenv_at(&root_path, None).is_err());
+
+        // nor is one that climbs out of the root
+        fs::remove_file(root_path.join("boot/grub2/grubenv")).unwrap();
+        std::os::unix::fs::symlink(
+            "../../../../../../../../../../etc/hostname",
+            root_path.join("boot/grub2/grubenv"),
+        )
+        .unwrap();
+        assert!(discover_grubenv_at(&root_path, None).is_err());
     }
 }
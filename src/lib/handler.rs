@@ -5,7 +5,8 @@ use serde_json::Value;
 use std::process::Command;
 use std::str;
 
-use crate::grub::get_boot_counter;
+use crate::store::BootStateStore;
+use crate::sysroot::{has_sysroot, rooted, sysroot};
 
 /// Detects if the system is managed by bootc or is a rpm-ostree system
 /// Inspect bootc status JSON and decide based on `status.booted.incompatible`.
@@ -53,9 +54,18 @@ pub fn detect_os_deployment() -> Option<&'static str> {
 }
 
 /// reboots the system if boot_counter is greater than 0 or can be forced too
-pub fn handle_reboot(force: bool) -> Result<()> {
+pub fn handle_reboot(store: &dyn BootStateStore, force: bool) -> Result<()> {
+    if has_sysroot() {
+        bail!(
+            "refusing to reboot, greenboot works on {}",
+            sysroot().display()
+        );
+    }
     if !force {
-        let boot_counter = get_boot_counter()?;
+        store
+            .verify()
+            .context("refusing to reboot, boot attempts would not be counted")?;
+        let boot_counter = store.get_boot_counter()?;
         if boot_counter <= Some(0) {
             bail!("countdown ended, check greenboot-rollback status")
         };
@@ -66,8 +76,8 @@ pub fn handle_reboot(force: bool) -> Result<()> {
 }
 
 /// Rollback to the previous deployment if the boot counter allows.
-pub fn handle_rollback() -> Result<()> {
-    let boot_counter = get_boot_counter()?;
+pub fn handle_rollback(store: &dyn BootStateStore) -> Result<()> {
+    let boot_counter = store.get_boot_counter()?;
 
     match boot_counter {
         // Exit early if boot_counter is not set
@@ -79,18 +89,7 @@ pub fn handle_rollback() -> Result<()> {
             log::info!("Greenboot will now attempt to rollback to a previous deployment.");
             if let Some(deployment_cmd) = detect_os_deployment() {
                 log::info!("Deployment manager '{deployment_cmd}' detected, attempting rollback.");
-                let status = Command::new(deployment_cmd)
-                    .arg("rollback")
-                    .status()
-                    .context(format!("Failed to execute '{deployment_cmd} rollback'"))?;
-
-                if !status.success() {
-                    bail!(
-                        "Rollback with '{}' failed with status: {}",
-                        deployment_cmd,
-                        status
-                    );
-                }
+                run_rollback(deployment_cmd)?;
             } else {
                 bail!("Rollback only supported in bootc or rpm-ostree environment.");
             }
@@ -101,8 +100,237 @@ pub fn handle_rollback() -> Result<()> {
     }
 }
 
+/// runs `<deployment_cmd> rollback`
+pub fn run_rollback(deployment_cmd: &str) -> Result<()> {
+    if has_sysroot() {
+        bail!(
+            "refusing to roll back, greenboot works on {}",
+            sysroot().display()
+        );
+    }
+    let status = Command::new(deployment_cmd)
+        .arg("rollback")
+        .status()
+        .context(format!("Failed to execute '{deployment_cmd} rollback'"))?;
+
+    if !status.success() {
+        bail!(
+            "Rollback with '{}' failed with status: {}",
+            deployment_cmd,
+            status
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What a rollback would change, see [`rollback_plan`]
+pub struct RollbackPlan {
+    /// deployment manager doing the rollback, bootc or rpm-ostree
+    pub manager: &'static str,
+    /// deployment booted by default now
+    pub current: String,
+    /// deployment that becomes the default
+    pub target: String,
+}
+
+/// Asks the deployment manager which deployment a rollback would make default.
+pub fn rollback_plan() -> Result<RollbackPlan> {
+    let Some(manager) = detect_os_deployment() else {
+        bail!("Rollback only supported in bootc or rpm-ostree environment.");
+    };
+    let output = Command::new(manager)
+        .args(["status", "--json"])
+        .output()
+        .context(format!("Failed to execute '{manager} status --json'"))?;
+    if !output.status.success() {
+        bail!(
+            "'{manager} status --json' failed with status: {}",
+            output.status
+        );
+    }
+    let json: Value = serde_json::from_slice(&output.stdout).context(format!(
+        "Failed to parse JSON from '{manager} status --json'"
+    ))?;
+    let (current, target) = match manager {
+        "bootc" => parse_bootc_rollback(&json)?,
+        _ => parse_rpm_ostree_rollback(&json)?,
+    };
+    Ok(RollbackPlan {
+        manager,
+        current,
+        target,
+    })
+}
+
+/// describes a deployment by image or origin, version and checksum
+fn describe_deployment(
+    reference: Option<&str>,
+    version: Option<&str>,
+    checksum: Option<&str>,
+) -> String {
+    let mut description = reference.unwrap_or("unknown deployment").to_string();
+    if let Some(version) = version {
+        description.push_str(&format!(" (version {version})"));
+    }
+    if let Some(checksum) = checksum {
+        let short: String = checksum.chars().take(12).collect();
+        description.push_str(&format!(" [{short}]"));
+    }
+    description
+}
+
+/// current and rollback deployment from `bootc status --json`
+fn parse_bootc_rollback(json: &Value) -> Result<(String, String)> {
+    let describe = |entry: &Value| {
+        let str_at = |pointer: &str| entry.pointer(pointer).and_then(Value::as_str);
+        describe_deployment(
+            str_at("/image/image/image"),
+            str_at("/image/version"),
+            str_at("/ostree/checksum"),
+        )
+    };
+    let status = json
+        .get("status")
+        .ok_or_else(|| anyhow!("bootc status JSON missing status"))?;
+    let current = status
+        .get("booted")
+        .filter(|b| !b.is_null())
+        .ok_or_else(|| anyhow!("bootc status reports no booted deployment"))?;
+    let rollback = status
+        .get("rollback")
+        .filter(|r| !r.is_null())
+        .ok_or_else(|| anyhow!("No rollback deployment available"))?;
+    Ok((describe(current), describe(rollback)))
+}
+
+/// current default and rollback deployment from `rpm-ostree status --json`
+///
+/// Like `rpm-ostree rollback`, the target is the booted deployment if it is not
+/// the default, otherwise the one after the default.
+fn parse_rpm_ostree_rollback(json: &Value) -> Result<(String, String)> {
+    let describe = |entry: &Value| {
+        let str_at = |key: &str| entry.get(key).and_then(Value::as_str);
+        describe_deployment(
+            str_at("container-image-reference").or(str_at("origin")),
+            str_at("version"),
+            str_at("checksum"),
+        )
+    };
+    let deployments = json
+        .get("deployments")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("rpm-ostree status JSON missing deployments"))?;
+    let default = deployments
+        .first()
+        .ok_or_else(|| anyhow!("rpm-ostree status reports no deployments"))?;
+    let booted = |entry: &Value| entry.get("booted").and_then(Value::as_bool) == Some(true);
+    let target = if booted(default) {
+        deployments.get(1)
+    } else {
+        deployments.iter().find(|entry| booted(entry))
+    }
+    .ok_or_else(|| anyhow!("No rollback deployment available"))?;
+    Ok((describe(default), describe(target)))
+}
+
 /// writes greenboot status to motd.d/boot-status
 pub fn handle_motd(state: &str) -> Result<()> {
-    std::fs::write("/etc/motd.d/boot-status", format!("{state}.").as_bytes())
-        .map_err(|err| anyhow!("Error writing motd: {}", err))
+    std::fs::write(
+        rooted("/etc/motd.d/boot-status"),
+        format!("{state}.").as_bytes(),
+    )
+    .map_err(|err| anyhow!("Error writing motd: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grub::Grub;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_reboot_refused_when_countdown_ended() {
+        let store = MemoryStore::new();
+        store.set_boot_counter(0).unwrap();
+        assert!(handle_reboot(&store, false).is_err());
+    }
+
+    #[test]
+    fn test_reboot_refused_when_boot_state_unreadable() {
+        let dir = tempfile::tempdir().unwrap();
+        let grubenv = dir.path().join("grubenv");
+        std::fs::write(&grubenv, "# GRUB Environment Block\nboot_counter=2\n").unwrap();
+        let store = Grub::new(grubenv.to_str().unwrap());
+        assert!(
+            handle_reboot(&store, false)
+                .unwrap_err()
+                .to_string()
+                .contains("refusing to reboot")
+        );
+    }
+
+    #[test]
+    fn test_parse_bootc_rollback() {
+        let status = serde_json::json!({
+            "status": {
+                "booted": {
+                    "image": {"image": {"image": "quay.io/example/os:v2"}, "version": "2.0"},
+                    "ostree": {"checksum": "0123456789abcdef0123"}
+                },
+                "rollback": {
+                    "image": {"image": {"image": "quay.io/example/os:v1"}, "version": null},
+                    "ostree": {"checksum": "fedcba9876543210"}
+                }
+            }
+        });
+        assert_eq!(
+            parse_bootc_rollback(&status).unwrap(),
+            (
+                "quay.io/example/os:v2 (version 2.0) [0123456789ab]".to_string(),
+                "quay.io/example/os:v1 [fedcba987654]".to_string()
+            )
+        );
+
+        let no_rollback = serde_json::json!({"status": {"booted": {}, "rollback": null}});
+        assert!(parse_bootc_rollback(&no_rollback).is_err());
+    }
+
+    #[test]
+    fn test_parse_rpm_ostree_rollback() {
+        let deployment = |version: &str, booted: bool| serde_json::json!({"origin": "fedora/39/x86_64/iot", "version": version, "booted": booted});
+        let status = serde_json::json!({
+            "deployments": [deployment("39.2", true), deployment("39.1", false)]
+        });
+        let (current, target) = parse_rpm_ostree_rollback(&status).unwrap();
+        assert_eq!(current, "fedora/39/x86_64/iot (version 39.2)");
+        assert_eq!(target, "fedora/39/x86_64/iot (version 39.1)");
+
+        // Booted into the second deployment, rolling back makes it default again
+        let status = serde_json::json!({
+            "deployments": [deployment("39.3", false), deployment("39.2", true)]
+        });
+        let (_, target) = parse_rpm_ostree_rollback(&status).unwrap();
+        assert_eq!(target, "fedora/39/x86_64/iot (version 39.2)");
+
+        let single = serde_json::json!({"deployments": [deployment("39.2", true)]});
+        assert!(parse_rpm_ostree_rollback(&single).is_err());
+    }
+
+    #[test]
+    fn test_rollback_requires_exhausted_counter() {
+        let store = MemoryStore::new();
+        assert!(
+            handle_rollback(&store)
+                .unwrap_err()
+                .to_string()
+                .contains("boot_counter is not set")
+        );
+
+        store.set_boot_counter(2).unwrap();
+        assert_eq!(
+            handle_rollback(&store).unwrap_err().to_string(),
+            "Rollback not initiated as boot_counter is 2"
+        );
+    }
 }
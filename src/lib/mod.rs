@@ -1,12 +1,24 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
+pub mod doctor;
 pub mod greenboot;
 pub mod grub;
 pub mod handler;
 pub mod mount;
+pub mod store;
+pub mod sysroot;
+pub mod systemd_boot;
+pub mod uboot;
+pub mod verdict;
 
 // Re-export public API
+pub use doctor::*;
 pub use greenboot::*;
 pub use grub::*;
 pub use handler::*;
 pub use mount::*;
+pub use store::*;
+pub use sysroot::*;
+pub use systemd_boot::*;
+pub use uboot::*;
+pub use verdict::*;
@@ -2,121 +2,185 @@
 
 use log::{info, warn};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 #[cfg(not(feature = "test-remount"))]
 use std::process::{Command, Stdio};
 use thiserror::Error;
 
+use crate::sysroot::rooted;
+
 /// Shared path to mount info used by default helpers
 static MOUNT_INFO_PATH: &str = "/proc/mounts";
 
+/// Mount point greenboot keeps its state on by default
+static BOOT_MOUNT_POINT: &str = "/boot";
+
 #[derive(Debug, Error)]
 pub enum MountError {
-    #[error("Failed to remount /boot: {0}")]
+    #[error("Failed to remount filesystem: {0}")]
     RemountFailed(String),
     #[error("Failed to read mount info")]
     MountInfoError,
 }
 
-fn is_boot_rw_at(mounts_path: &Path) -> Result<bool, MountError> {
-    let mounts = fs::read_to_string(mounts_path).map_err(|_| MountError::MountInfoError)?;
-    for line in mounts.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 4 && parts.get(1) == Some(&"/boot") {
-            let options = parts[3];
-            return Ok(options.contains("rw") && !options.contains("ro"));
+/// decodes the octal escapes (`\040` for space, ...) used in /proc/mounts
+fn unescape_mount_field(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(&[a, b, c]) = bytes.get(i + 1..i + 4)
+            && (b'0'..=b'3').contains(&a)
+            && (b'0'..=b'7').contains(&b)
+            && (b'0'..=b'7').contains(&c)
+        {
+            out.push((a - b'0') << 6 | (b - b'0') << 3 | (c - b'0'));
+            i += 4;
+            continue;
         }
+        out.push(bytes[i]);
+        i += 1;
     }
-    Err(MountError::MountInfoError)
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Returns the mount options of `mount_point`. With stacked mounts the last
+/// entry is the one that is visible, so that one wins.
+fn mount_options_at(mounts_path: &Path, mount_point: &Path) -> Result<String, MountError> {
+    let mounts = fs::read_to_string(mounts_path).map_err(|_| MountError::MountInfoError)?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            (parts.len() >= 4 && Path::new(&unescape_mount_field(parts[1])) == mount_point)
+                .then(|| parts[3].to_string())
+        })
+        .next_back()
+        .ok_or(MountError::MountInfoError)
+}
+
+fn is_mount_rw_at(mounts_path: &Path, mount_point: &Path) -> Result<bool, MountError> {
+    let options = mount_options_at(mounts_path, mount_point)?;
+    Ok(options.split(',').any(|o| o == "rw"))
+}
+
+fn find_mount_point_at(mounts_path: &Path, path: &Path) -> Result<PathBuf, MountError> {
+    let mounts = fs::read_to_string(mounts_path).map_err(|_| MountError::MountInfoError)?;
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|mount_point| PathBuf::from(unescape_mount_field(mount_point)))
+        .filter(|mount_point| path.starts_with(mount_point))
+        .max_by_key(|mount_point| mount_point.components().count())
+        .ok_or(MountError::MountInfoError)
+}
+
+/// Finds the mount point of the filesystem holding `path`, symlinks resolved
+pub fn find_mount_point(path: &Path) -> Result<PathBuf, MountError> {
+    find_mount_point_at(&rooted(MOUNT_INFO_PATH), path)
+}
+
+/// Default helper: check RW state of `mount_point` using shared MOUNT_INFO_PATH
+pub fn is_mount_rw(mount_point: &Path) -> Result<bool, MountError> {
+    is_mount_rw_at(&rooted(MOUNT_INFO_PATH), mount_point)
+}
+
+fn is_boot_rw_at(mounts_path: &Path) -> Result<bool, MountError> {
+    is_mount_rw_at(mounts_path, Path::new(BOOT_MOUNT_POINT))
 }
 
 /// Default helper: check /boot RW state using shared MOUNT_INFO_PATH
 pub fn is_boot_rw() -> Result<bool, MountError> {
-    is_boot_rw_at(Path::new(MOUNT_INFO_PATH))
+    is_boot_rw_at(&rooted(MOUNT_INFO_PATH))
 }
 
 #[cfg(not(feature = "test-remount"))]
-fn remount_boot_ro_at(mounts_path: &Path) -> Result<(), MountError> {
-    match is_boot_rw_at(mounts_path)? {
-        true => {
-            let output = Command::new("mount")
-                .arg("-o")
-                .arg("remount,bind,ro")
-                .arg("/boot")
-                .stderr(Stdio::piped()) // Capture stderr for error handling
-                .output();
-
-            match output {
-                Ok(output) => {
-                    if output.status.success() {
-                        Ok(())
-                    } else {
-                        let error_message = String::from_utf8_lossy(&output.stderr);
-                        warn!("Failed to remount /boot as RO using shell: {error_message}");
-                        Err(MountError::RemountFailed(error_message.to_string()))
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to execute mount command: {e}");
-                    Err(MountError::RemountFailed(format!(
-                        "Failed to execute mount: {e}"
-                    )))
-                }
+fn run_remount(options: &str, mount_point: &Path) -> Result<(), MountError> {
+    let output = Command::new("mount")
+        .arg("-o")
+        .arg(options)
+        .arg(mount_point)
+        .stderr(Stdio::piped()) // Capture stderr for error handling
+        .output();
+
+    match output {
+        Ok(output) => {
+            if output.status.success() {
+                Ok(())
+            } else {
+                let error_message = String::from_utf8_lossy(&output.stderr);
+                warn!(
+                    "Failed to remount {} with {options} using shell: {error_message}",
+                    mount_point.display()
+                );
+                Err(MountError::RemountFailed(error_message.to_string()))
             }
         }
-        false => {
-            info!("/boot is already read-only");
-            Ok(())
+        Err(e) => {
+            warn!("Failed to execute mount command: {e}");
+            Err(MountError::RemountFailed(format!(
+                "Failed to execute mount: {e}"
+            )))
         }
     }
 }
 
 #[cfg(not(feature = "test-remount"))]
-fn remount_boot_rw_at(mounts_path: &Path) -> Result<(), MountError> {
-    match is_boot_rw_at(mounts_path)? {
+fn remount_ro_at(mounts_path: &Path, mount_point: &Path) -> Result<(), MountError> {
+    match is_mount_rw_at(mounts_path, mount_point)? {
+        true => run_remount("remount,bind,ro", mount_point),
         false => {
-            let output = Command::new("mount")
-                .arg("-o")
-                .arg("remount,rw")
-                .arg("/boot")
-                .stderr(Stdio::piped()) // Capture stderr for error handling
-                .output();
-
-            match output {
-                Ok(output) => {
-                    if output.status.success() {
-                        Ok(())
-                    } else {
-                        let error_message = String::from_utf8_lossy(&output.stderr);
-                        warn!("Failed to remount /boot as RW using shell: {error_message}");
-                        Err(MountError::RemountFailed(error_message.to_string()))
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to execute mount command: {e}");
-                    Err(MountError::RemountFailed(format!(
-                        "Failed to execute mount: {e}"
-                    )))
-                }
-            }
+            info!("{} is already read-only", mount_point.display());
+            Ok(())
         }
+    }
+}
+
+#[cfg(not(feature = "test-remount"))]
+fn remount_rw_at(mounts_path: &Path, mount_point: &Path) -> Result<(), MountError> {
+    match is_mount_rw_at(mounts_path, mount_point)? {
+        false => run_remount("remount,rw", mount_point),
         true => {
-            info!("/boot is already read-write");
+            info!("{} is already read-write", mount_point.display());
             Ok(())
         }
     }
 }
 
+#[cfg(not(feature = "test-remount"))]
+fn remount_boot_ro_at(mounts_path: &Path) -> Result<(), MountError> {
+    remount_ro_at(mounts_path, Path::new(BOOT_MOUNT_POINT))
+}
+
+#[cfg(not(feature = "test-remount"))]
+fn remount_boot_rw_at(mounts_path: &Path) -> Result<(), MountError> {
+    remount_rw_at(mounts_path, Path::new(BOOT_MOUNT_POINT))
+}
+
+/// Default helper: remount `mount_point` RO using shared MOUNT_INFO_PATH
+#[cfg(not(feature = "test-remount"))]
+pub fn remount_ro(mount_point: &Path) -> Result<(), MountError> {
+    remount_ro_at(&rooted(MOUNT_INFO_PATH), mount_point)
+}
+
+/// Default helper: remount `mount_point` RW using shared MOUNT_INFO_PATH
+#[cfg(not(feature = "test-remount"))]
+pub fn remount_rw(mount_point: &Path) -> Result<(), MountError> {
+    remount_rw_at(&rooted(MOUNT_INFO_PATH), mount_point)
+}
+
 /// Default helper: remount /boot RO using shared MOUNT_INFO_PATH
 #[cfg(not(feature = "test-remount"))]
 pub fn remount_boot_ro() -> Result<(), MountError> {
-    remount_boot_ro_at(Path::new(MOUNT_INFO_PATH))
+    remount_boot_ro_at(&rooted(MOUNT_INFO_PATH))
 }
 
 /// Default helper: remount /boot RW using shared MOUNT_INFO_PATH
 #[cfg(not(feature = "test-remount"))]
 pub fn remount_boot_rw() -> Result<(), MountError> {
-    remount_boot_rw_at(Path::new(MOUNT_INFO_PATH))
+    remount_boot_rw_at(&rooted(MOUNT_INFO_PATH))
 }
 
 /// For testing without actually remounting /mount
@@ -139,6 +203,14 @@ pub fn remount_boot_rw() -> Result<(), MountError> {
 pub fn remount_boot_ro() -> Result<(), MountError> {
     Ok(())
 }
+#[cfg(feature = "test-remount")]
+pub fn remount_rw(_mount_point: &Path) -> Result<(), MountError> {
+    Ok(())
+}
+#[cfg(feature = "test-remount")]
+pub fn remount_ro(_mount_point: &Path) -> Result<(), MountError> {
+    Ok(())
+}
 
 #[cfg(test)]
 mod test {
@@ -192,4 +264,64 @@ mod test {
         let malformed_path = create_mock_file("incomplete fields");
         assert!(is_boot_rw_at(&malformed_path).is_err());
     }
+
+    #[test]
+    fn test_is_mount_rw_exact_options() {
+        // errors=remount-ro must not be mistaken for ro
+        let path = create_mock_file(
+            "/dev/nvme0n1p1 /boot/efi vfat rw,relatime,errors=remount-ro 0 0\n\
+             /dev/nvme0n1p2 /boot ext4 ro,seclabel,relatime 0 0\n",
+        );
+        assert!(is_mount_rw_at(&path, Path::new("/boot/efi")).unwrap());
+        assert!(!is_mount_rw_at(&path, Path::new("/boot")).unwrap());
+    }
+
+    #[test]
+    fn test_find_mount_point() {
+        let path = create_mock_file(
+            "rootfs / rootfs rw 0 0\n\
+             /dev/nvme0n1p2 /boot ext4 ro 0 0\n\
+             /dev/nvme0n1p1 /boot/efi vfat rw 0 0\n\
+             /dev/sda1 /mnt/my\\040disk ext4 rw 0 0\n",
+        );
+        assert_eq!(
+            find_mount_point_at(&path, Path::new("/boot/grub2/grubenv")).unwrap(),
+            Path::new("/boot")
+        );
+        assert_eq!(
+            find_mount_point_at(&path, Path::new("/boot/efi/EFI/fedora/grubenv")).unwrap(),
+            Path::new("/boot/efi")
+        );
+        assert_eq!(
+            find_mount_point_at(&path, Path::new("/mnt/my disk/grubenv")).unwrap(),
+            Path::new("/mnt/my disk")
+        );
+        assert_eq!(
+            find_mount_point_at(&path, Path::new("/etc/greenboot")).unwrap(),
+            Path::new("/")
+        );
+    }
+
+    #[test]
+    fn test_unescape_mount_field() {
+        assert_eq!(unescape_mount_field("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(unescape_mount_field("/mnt/tab\\011"), "/mnt/tab\t");
+        // not an escape, kept as is
+        assert_eq!(unescape_mount_field("/mnt/a\\08"), "/mnt/a\\08");
+        assert_eq!(unescape_mount_field("/mnt/a\\400"), "/mnt/a\\400");
+        assert_eq!(unescape_mount_field("/mnt/a\\"), "/mnt/a\\");
+        // a multibyte character right after the backslash must not panic
+        assert_eq!(unescape_mount_field("/mnt/\\é1"), "/mnt/\\é1");
+        assert_eq!(unescape_mount_field("/mnt/\\1é"), "/mnt/\\1é");
+        assert_eq!(unescape_mount_field("/mnt/\\12é"), "/mnt/\\12é");
+    }
+
+    #[test]
+    fn test_stacked_mount_uses_last_entry() {
+        let path = create_mock_file(
+            "/dev/sda2 /boot ext4 rw 0 0\n\
+             /dev/sda2 /boot ext4 ro 0 0\n",
+        );
+        assert!(!is_boot_rw_at(&path).unwrap());
+    }
 }
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::grub::{BootCountState, BootState, BootStateOp};
+
+/// Storage of greenboot's boot state in a bootloader.
+///
+/// Backends only have to read the counter and trigger and apply a
+/// [`BootState`] transaction, the single variable helpers are built on
+/// top of [`BootStateStore::commit`].
+pub trait BootStateStore: fmt::Debug + Send + Sync {
+    /// short backend name used in logs
+    fn name(&self) -> &'static str;
+
+    /// fetches boot_counter value, none if not set
+    fn get_boot_counter(&self) -> Result<Option<i32>>;
+
+    /// gets the rollback trigger, true if set
+    fn get_rollback_trigger(&self) -> Result<bool>;
+
+    /// gets boot_success, none if the bootloader does not track it
+    fn get_boot_success(&self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// where boot counting stands, derived from the boot counter by default
+    fn get_boot_count_state(&self) -> Result<BootCountState> {
+        Ok(BootCountState::from_counter(self.get_boot_counter()?))
+    }
+
+    /// applies all operations of `state` in one write
+    fn commit(&self, state: BootState) -> Result<()>;
+
+    /// file the state is written to, used to find the filesystem to remount rw
+    fn storage_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// checks that the bootloader can read the stored state, without it
+    /// boot attempts are not counted down and a reboot may loop forever
+    fn verify(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// sets boot_counter if not set, a counter that cannot be read is overwritten
+    fn set_boot_counter(&self, reboot_count: u16) -> Result<()> {
+        match self.get_boot_counter() {
+            Ok(Some(i)) => bail!("already set boot_counter={i}"),
+            Ok(None) => {}
+            Err(e) => log::warn!("overwriting boot_counter: {e}"),
+        }
+        log::info!("setting boot counter");
+        self.commit(BootState::new().set_boot_counter(reboot_count))
+    }
+
+    /// unset boot_counter
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.commit(BootState::new().unset_boot_counter())
+    }
+
+    /// sets boot_success, a successful boot also clears boot_counter
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        self.commit(BootState::new().set_boot_status(success))
+    }
+
+    /// sets the rollback trigger
+    fn set_rollback_trigger(&self) -> Result<()> {
+        self.commit(BootState::new().set_rollback_trigger())
+    }
+
+    /// unsets the rollback trigger
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        self.commit(BootState::new().unset_rollback_trigger())
+    }
+}
+
+#[derive(Debug, Default)]
+/// In-memory boot state, for tests and dry runs that must not touch a bootloader
+pub struct MemoryStore {
+    vars: Mutex<BTreeMap<String, String>>,
+}
+
+impl MemoryStore {
+    /// creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// returns the value of `key`, none if not set
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.vars.lock().unwrap().get(key).cloned()
+    }
+}
+
+impl BootStateStore for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get_boot_counter(&self) -> Result<Option<i32>> {
+        match self.get("boot_counter") {
+            Some(v) => match v.parse::<i32>() {
+                Ok(n) => Ok(Some(n)),
+                Err(_) => bail!("boot_counter has invalid value: {v}"),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn get_rollback_trigger(&self) -> Result<bool> {
+        Ok(self.get("greenboot_rollback_trigger").as_deref() == Some("1"))
+    }
+
+    fn get_boot_success(&self) -> Result<Option<bool>> {
+        Ok(self.get("boot_success").map(|v| v == "1"))
+    }
+
+    fn commit(&self, state: BootState) -> Result<()> {
+        let mut vars = self.vars.lock().unwrap();
+        for op in state.ops() {
+            match op {
+                BootStateOp::Set(key, value) => {
+                    vars.insert(key.clone(), value.clone());
+                }
+                BootStateOp::Unset(key) => {
+                    vars.remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_boot_counter() {
+        let store = MemoryStore::new();
+        assert_eq!(store.get_boot_counter().unwrap(), None);
+        store.set_boot_counter(3).unwrap();
+        assert_eq!(store.get_boot_counter().unwrap(), Some(3));
+        assert!(store.set_boot_counter(5).is_err());
+
+        store
+            .commit(BootState::new().set("boot_counter", "foo"))
+            .unwrap();
+        assert!(store.get_boot_counter().is_err());
+        store.set_boot_counter(4).unwrap();
+        assert_eq!(store.get_boot_counter().unwrap(), Some(4));
+
+        store.set_boot_status(true).unwrap();
+        assert_eq!(store.get_boot_counter().unwrap(), None);
+        assert_eq!(store.get("boot_success").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_memory_store_rollback_trigger() {
+        let store = MemoryStore::new();
+        assert!(!store.get_rollback_trigger().unwrap());
+        store.set_rollback_trigger().unwrap();
+        assert!(store.get_rollback_trigger().unwrap());
+        store.unset_rollback_trigger().unwrap();
+        assert!(!store.get_rollback_trigger().unwrap());
+    }
+
+    #[test]
+    fn test_memory_store_usable_as_trait_object() {
+        let store: Box<dyn BootStateStore> = Box::new(MemoryStore::new());
+        store
+            .commit(BootState::new().set_boot_status(false).set_boot_counter(2))
+            .unwrap();
+        assert_eq!(store.get_boot_counter().unwrap(), Some(2));
+        assert_eq!(store.name(), "memory");
+    }
+}
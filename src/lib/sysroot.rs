@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// directory all of greenboot's paths are resolved below, `/` if unset
+static SYSROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Resolves all default paths below `root` instead of `/`, e.g. to inspect an
+/// unbooted image tree. Can only be set once, before any path is used.
+pub fn set_sysroot(root: &Path) -> Result<()> {
+    if !root.is_dir() {
+        bail!("sysroot {} is not a directory", root.display());
+    }
+    let root = root.canonicalize()?;
+    if SYSROOT.set(root).is_err() {
+        bail!("sysroot is already set to {}", sysroot().display());
+    }
+    Ok(())
+}
+
+/// directory greenboot's paths are resolved below
+pub fn sysroot() -> &'static Path {
+    SYSROOT.get().map_or(Path::new("/"), PathBuf::as_path)
+}
+
+/// true if greenboot works on a tree other than the running system
+pub fn has_sysroot() -> bool {
+    sysroot() != Path::new("/")
+}
+
+/// absolute `path` of the running system, resolved below the sysroot
+pub fn rooted(path: impl AsRef<Path>) -> PathBuf {
+    rooted_in(sysroot(), path.as_ref())
+}
+
+fn rooted_in(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rooted_in() {
+        let root = Path::new("/sysroot");
+        assert_eq!(
+            rooted_in(root, Path::new("/etc/greenboot")),
+            Path::new("/sysroot/etc/greenboot")
+        );
+        assert_eq!(
+            rooted_in(Path::new("/"), Path::new("/etc/greenboot")),
+            Path::new("/etc/greenboot")
+        );
+        assert_eq!(
+            rooted_in(root, Path::new("boot")),
+            Path::new("/sysroot/boot")
+        );
+    }
+}
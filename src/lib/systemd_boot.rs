@@ -0,0 +1,484 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::grub::{BootState, BootStateOp};
+use crate::store::BootStateStore;
+use crate::sysroot::rooted;
+
+/// Default mount point of the ESP/XBOOTLDR partition holding the loader entries
+pub static ESP_PATH: &str = "/boot";
+
+/// efivarfs mount that systemd-boot exports its loader interface to
+static EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+
+/// Vendor GUID of the systemd-boot loader interface variables
+const LOADER_GUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+
+/// Marker kept next to the loader entries in place of greenboot_rollback_trigger
+const ROLLBACK_TRIGGER_FILE: &str = "loader/greenboot-rollback-trigger";
+
+#[derive(Debug, Error)]
+pub enum SystemdBootError {
+    #[error("Cannot determine the booted loader entry: {0}")]
+    EntryNotFound(String),
+    #[error("Failed to read EFI variable {0}: {1}")]
+    EfiVarError(String, std::io::Error),
+    #[error("Failed to rename loader entry {0} to {1}: {2}")]
+    RenameFailed(String, String, std::io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// File name of a loader entry split into its id and boot counting suffix.
+///
+/// systemd-boot encodes boot counting as `<id>+LEFT[-DONE]<ext>`, e.g.
+/// `fedora+2-1.conf` has two tries left and one failed try done. Entries
+/// without the suffix are considered good and are not counted.
+pub struct EntryName {
+    /// file name with the counting suffix removed, e.g. `fedora.conf`
+    pub id: String,
+    /// tries left, none if the entry is not counted
+    pub left: Option<u16>,
+    /// tries done, none if not recorded
+    pub done: Option<u16>,
+}
+
+impl EntryName {
+    /// splits a loader entry file name into id and counters
+    pub fn parse(file_name: &str) -> Self {
+        let (stem, ext) = match file_name.rfind('.') {
+            Some(i) if i > 0 => file_name.split_at(i),
+            _ => (file_name, ""),
+        };
+        if let Some((base, counter)) = stem.rsplit_once('+')
+            && !base.is_empty()
+        {
+            let counters = match counter.split_once('-') {
+                Some((left, done)) => left
+                    .parse::<u16>()
+                    .ok()
+                    .zip(done.parse::<u16>().ok().map(Some)),
+                None => counter.parse::<u16>().ok().map(|left| (left, None)),
+            };
+            if let Some((left, done)) = counters {
+                return Self {
+                    id: format!("{base}{ext}"),
+                    left: Some(left),
+                    done,
+                };
+            }
+        }
+        Self {
+            id: file_name.to_string(),
+            left: None,
+            done: None,
+        }
+    }
+
+    /// builds the on-disk file name for the current counters
+    pub fn file_name(&self) -> String {
+        let (stem, ext) = match self.id.rfind('.') {
+            Some(i) if i > 0 => self.id.split_at(i),
+            _ => (self.id.as_str(), ""),
+        };
+        match (self.left, self.done) {
+            (Some(left), Some(done)) => format!("{stem}+{left}-{done}{ext}"),
+            (Some(left), None) => format!("{stem}+{left}{ext}"),
+            (None, _) => self.id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Boot counting backend for systemd-boot.
+///
+/// Boot state lives in the name of the booted loader entry (see
+/// [`EntryName`]), so greenboot's boot_counter maps to the tries left and
+/// marking a boot successful drops the suffix like `systemd-bless-boot good`.
+/// The booted entry is taken from the configured path or looked up through
+/// the `LoaderBootCountPath`/`LoaderEntrySelected` EFI variables.
+pub struct SystemdBoot {
+    esp: PathBuf,
+    efivars: PathBuf,
+    entry: Option<PathBuf>,
+}
+
+impl Default for SystemdBoot {
+    fn default() -> Self {
+        Self::new(rooted(ESP_PATH))
+    }
+}
+
+impl SystemdBoot {
+    /// backend for loader entries below `esp`
+    pub fn new(esp: impl Into<PathBuf>) -> Self {
+        Self {
+            esp: esp.into(),
+            efivars: rooted(EFIVARS_PATH),
+            entry: None,
+        }
+    }
+
+    /// uses `entry` as booted entry instead of asking the firmware
+    pub fn with_entry(mut self, entry: impl Into<PathBuf>) -> Self {
+        self.entry = Some(entry.into());
+        self
+    }
+
+    /// reads the loader EFI variables from `efivars` instead of efivarfs
+    pub fn with_efivars(mut self, efivars: impl Into<PathBuf>) -> Self {
+        self.efivars = efivars.into();
+        self
+    }
+
+    /// ESP/XBOOTLDR mount point the entries are looked up in
+    pub fn esp(&self) -> &Path {
+        &self.esp
+    }
+
+    /// Resolves the current path of the booted loader entry.
+    ///
+    /// Entries are matched by id, so the lookup keeps working after the
+    /// counting suffix has been changed during this boot.
+    pub fn booted_entry(&self) -> Result<PathBuf, SystemdBootError> {
+        if let Some(entry) = &self.entry {
+            return find_entry(entry);
+        }
+
+        if let Some(count_path) = self.read_loader_var("LoaderBootCountPath")? {
+            let relative = count_path.trim_start_matches('\\').replace('\\', "/");
+            return find_entry(&self.esp.join(relative));
+        }
+
+        if let Some(selected) = self.read_loader_var("LoaderEntrySelected")? {
+            let id = if selected.ends_with(".conf") {
+                selected
+            } else {
+                format!("{selected}.conf")
+            };
+            return find_entry(&self.esp.join("loader/entries").join(id));
+        }
+
+        Err(SystemdBootError::EntryNotFound(String::from(
+            "neither LoaderBootCountPath nor LoaderEntrySelected is set",
+        )))
+    }
+
+    /// Reads a loader interface variable, none if systemd-boot did not set it.
+    /// efivarfs files carry 4 bytes of attributes followed by a NUL terminated UTF-16LE string.
+    fn read_loader_var(&self, name: &str) -> Result<Option<String>, SystemdBootError> {
+        let var_name = format!("{name}-{LOADER_GUID}");
+        let raw = match fs::read(self.efivars.join(&var_name)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SystemdBootError::EfiVarError(var_name, e)),
+        };
+        let utf16: Vec<u16> = raw
+            .get(4..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        let value = String::from_utf16_lossy(&utf16);
+        Ok((!value.is_empty()).then_some(value))
+    }
+
+    fn rollback_trigger_path(&self) -> PathBuf {
+        self.esp.join(ROLLBACK_TRIGGER_FILE)
+    }
+
+    fn staged_entry<'a>(
+        &self,
+        entry: &'a mut Option<(PathBuf, EntryName)>,
+    ) -> Result<&'a mut (PathBuf, EntryName), SystemdBootError> {
+        if entry.is_none() {
+            let path = self.booted_entry()?;
+            let name = entry_name(&path);
+            *entry = Some((path, name));
+        }
+        Ok(entry.as_mut().expect("entry staged above"))
+    }
+}
+
+impl BootStateStore for SystemdBoot {
+    fn name(&self) -> &'static str {
+        "systemd-boot"
+    }
+
+    fn get_boot_counter(&self) -> Result<Option<i32>> {
+        let entry = self.booted_entry()?;
+        Ok(entry_name(&entry).left.map(i32::from))
+    }
+
+    fn get_rollback_trigger(&self) -> Result<bool> {
+        Ok(self.rollback_trigger_path().exists())
+    }
+
+    /// an entry is blessed once its counting suffix is gone
+    fn get_boot_success(&self) -> Result<Option<bool>> {
+        let name = entry_name(&self.booted_entry()?);
+        Ok(Some(name.left.is_none() && name.done.is_none()))
+    }
+
+    fn storage_path(&self) -> Option<PathBuf> {
+        Some(self.esp.clone())
+    }
+
+    /// All counter changes are folded into a single rename of the booted
+    /// entry, which is atomic; the rollback trigger marker is updated after.
+    fn commit(&self, state: BootState) -> Result<()> {
+        if state.is_empty() {
+            return Ok(());
+        }
+
+        let mut entry: Option<(PathBuf, EntryName)> = None;
+        let mut trigger = None;
+        for op in state.ops() {
+            let (key, value) = match op {
+                BootStateOp::Set(key, value) => (key.as_str(), Some(value.as_str())),
+                BootStateOp::Unset(key) => (key.as_str(), None),
+            };
+            match (key, value) {
+                ("boot_success", Some("1")) | ("boot_counter", None) => {
+                    let (_, name) = self.staged_entry(&mut entry)?;
+                    name.left = None;
+                    name.done = None;
+                }
+                ("boot_success", _) => {
+                    log::debug!("systemd-boot counts failed boots itself, ignoring {op:?}");
+                }
+                ("boot_counter", Some(value)) => {
+                    let left = value
+                        .parse::<u16>()
+                        .map_err(|_| anyhow::anyhow!("boot_counter has invalid value: {value}"))?;
+                    let (_, name) = self.staged_entry(&mut entry)?;
+                    name.left = Some(left);
+                    name.done = None;
+                }
+                ("greenboot_rollback_trigger", value) => trigger = Some(value == Some("1")),
+                ("boot_indeterminate", _) => {
+                    log::debug!("systemd-boot has no indeterminate boots, ignoring {op:?}");
+                }
+                _ => log::warn!("Ignoring {op:?}, not supported by systemd-boot"),
+            }
+        }
+
+        if let Some((path, name)) = entry {
+            let target = path.with_file_name(name.file_name());
+            if target != path {
+                fs::rename(&path, &target).map_err(|e| {
+                    SystemdBootError::RenameFailed(
+                        path.display().to_string(),
+                        target.display().to_string(),
+                        e,
+                    )
+                })?;
+                if let Some(dir) = target.parent() {
+                    fs::File::open(dir)?.sync_all()?;
+                }
+                log::info!(
+                    "Renamed loader entry {} to {}",
+                    path.display(),
+                    target.display()
+                );
+            }
+        }
+
+        match trigger {
+            Some(true) => {
+                let marker = self.rollback_trigger_path();
+                fs::write(&marker, b"1\n")?;
+                fs::File::open(&marker)?.sync_all()?;
+                log::info!("Set rollback trigger {}", marker.display());
+            }
+            Some(false) => {
+                let marker = self.rollback_trigger_path();
+                if marker.exists() {
+                    fs::remove_file(&marker)?;
+                }
+                log::info!("Clear rollback trigger {}", marker.display());
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn entry_name(path: &Path) -> EntryName {
+    EntryName::parse(
+        &path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    )
+}
+
+/// finds the file currently holding the entry `path` refers to, whatever its counters are
+fn find_entry(path: &Path) -> Result<PathBuf, SystemdBootError> {
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let id = entry_name(path).id;
+    let entries = fs::read_dir(dir)
+        .map_err(|e| SystemdBootError::EntryNotFound(format!("{}: {e}", dir.display())))?;
+    entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .find(|p| p.is_file() && entry_name(p).id == id)
+        .ok_or_else(|| {
+            SystemdBootError::EntryNotFound(format!("no entry {id} in {}", dir.display()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{TempDir, tempdir};
+
+    fn write_efivar(efivars: &Path, name: &str, value: &str) {
+        let mut raw = vec![0x06, 0, 0, 0];
+        for c in value.encode_utf16().chain(std::iter::once(0)) {
+            raw.extend_from_slice(&c.to_le_bytes());
+        }
+        fs::write(efivars.join(format!("{name}-{LOADER_GUID}")), raw).unwrap();
+    }
+
+    /// fake ESP with one counted entry and the matching LoaderBootCountPath
+    fn setup_esp(entry: &str) -> (TempDir, SystemdBoot) {
+        let dir = tempdir().unwrap();
+        let esp = dir.path().join("boot");
+        let efivars = dir.path().join("efivars");
+        fs::create_dir_all(esp.join("loader/entries")).unwrap();
+        fs::create_dir_all(&efivars).unwrap();
+        fs::write(esp.join("loader/entries").join(entry), "title Fedora\n").unwrap();
+        fs::write(esp.join("loader/entries/other.conf"), "title Other\n").unwrap();
+        write_efivar(
+            &efivars,
+            "LoaderBootCountPath",
+            &format!("\\loader\\entries\\{entry}"),
+        );
+        let backend = SystemdBoot::new(esp).with_efivars(efivars);
+        (dir, backend)
+    }
+
+    fn entries(backend: &SystemdBoot) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(backend.esp().join("loader/entries"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_entry_name_parse() {
+        let name = EntryName::parse("fedora+2-1.conf");
+        assert_eq!(name.id, "fedora.conf");
+        assert_eq!((name.left, name.done), (Some(2), Some(1)));
+        assert_eq!(name.file_name(), "fedora+2-1.conf");
+
+        let name = EntryName::parse("fedora+3.conf");
+        assert_eq!((name.left, name.done), (Some(3), None));
+
+        let name = EntryName::parse("fedora-6.8+x.conf");
+        assert_eq!(name.id, "fedora-6.8+x.conf");
+        assert_eq!(name.left, None);
+
+        let name = EntryName::parse("linux+0-3.efi");
+        assert_eq!(name.id, "linux.efi");
+        assert_eq!(name.left, Some(0));
+    }
+
+    #[test]
+    fn test_get_boot_counter_from_efivar() {
+        let (_dir, backend) = setup_esp("fedora+2-1.conf");
+        assert_eq!(backend.get_boot_counter().unwrap(), Some(2));
+
+        let (_dir, backend) = setup_esp("fedora.conf");
+        assert_eq!(backend.get_boot_counter().unwrap(), None);
+    }
+
+    #[test]
+    fn test_bless_boot() {
+        let (_dir, backend) = setup_esp("fedora+1-2.conf");
+        backend.set_boot_status(false).unwrap();
+        assert_eq!(entries(&backend), ["fedora+1-2.conf", "other.conf"]);
+
+        backend.set_boot_status(true).unwrap();
+        assert_eq!(entries(&backend), ["fedora.conf", "other.conf"]);
+        assert_eq!(backend.get_boot_counter().unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_boot_counter() {
+        let (_dir, backend) = setup_esp("fedora.conf");
+        backend.set_boot_counter(3).unwrap();
+        assert_eq!(entries(&backend), ["fedora+3.conf", "other.conf"]);
+        assert_eq!(backend.get_boot_counter().unwrap(), Some(3));
+
+        // an existing counter is not overwritten
+        assert!(backend.set_boot_counter(5).is_err());
+        backend.unset_boot_counter().unwrap();
+        assert_eq!(entries(&backend), ["fedora.conf", "other.conf"]);
+
+        // a malformed suffix is part of the name, the entry is not counted yet
+        let (_dir, backend) = setup_esp("fedora+foo.conf");
+        assert_eq!(backend.get_boot_counter().unwrap(), None);
+        backend.set_boot_counter(3).unwrap();
+        assert_eq!(entries(&backend), ["fedora+foo+3.conf", "other.conf"]);
+    }
+
+    #[test]
+    fn test_entry_selected_fallback() {
+        let dir = tempdir().unwrap();
+        let esp = dir.path().join("boot");
+        fs::create_dir_all(esp.join("loader/entries")).unwrap();
+        fs::write(esp.join("loader/entries/fedora+0-3.conf"), "").unwrap();
+        write_efivar(dir.path(), "LoaderEntrySelected", "fedora.conf");
+
+        let backend = SystemdBoot::new(&esp).with_efivars(dir.path());
+        assert_eq!(backend.get_boot_counter().unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_configured_entry() {
+        let (dir, _) = setup_esp("fedora+2.conf");
+        let esp = dir.path().join("boot");
+        let backend = SystemdBoot::new(&esp)
+            .with_efivars(dir.path().join("missing"))
+            .with_entry(esp.join("loader/entries/other.conf"));
+        backend.set_boot_counter(1).unwrap();
+        assert!(esp.join("loader/entries/other+1.conf").is_file());
+        assert!(esp.join("loader/entries/fedora+2.conf").is_file());
+    }
+
+    #[test]
+    fn test_no_booted_entry() {
+        let dir = tempdir().unwrap();
+        let backend = SystemdBoot::new(dir.path()).with_efivars(dir.path());
+        assert!(backend.get_boot_counter().is_err());
+    }
+
+    #[test]
+    fn test_rollback_trigger_and_transaction() {
+        let (_dir, backend) = setup_esp("fedora+1-2.conf");
+        assert!(!backend.get_rollback_trigger().unwrap());
+        backend.set_rollback_trigger().unwrap();
+        assert!(backend.get_rollback_trigger().unwrap());
+
+        backend
+            .commit(
+                BootState::new()
+                    .set_boot_status(true)
+                    .unset_rollback_trigger(),
+            )
+            .unwrap();
+        assert!(!backend.get_rollback_trigger().unwrap());
+        assert_eq!(entries(&backend), ["fedora.conf", "other.conf"]);
+    }
+}
@@ -0,0 +1,563 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::Result;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::grub::{BootState, BootStateOp};
+use crate::store::BootStateStore;
+use crate::sysroot::rooted;
+
+/// Default location of the U-Boot environment image
+pub static UBOOT_ENV_PATH: &str = "/boot/uboot.env";
+
+/// Default size of one environment copy, CONFIG_ENV_SIZE in U-Boot
+pub const UBOOT_ENV_SIZE: usize = 0x4000;
+
+#[derive(Debug, Error)]
+pub enum UBootEnvError {
+    #[error("Failed to read U-Boot env {0}: {1}")]
+    ReadFailed(String, std::io::Error),
+    #[error("Failed to write U-Boot env {0}: {1}")]
+    WriteFailed(String, std::io::Error),
+    #[error("U-Boot env {0} has no copy with a valid CRC")]
+    BadCrc(String),
+    #[error("U-Boot env variables need {0} bytes, more than the environment size")]
+    EnvFull(usize),
+    #[error("Invalid U-Boot env key: {0:?}")]
+    InvalidKey(String),
+}
+
+/// CRC32 (IEEE 802.3) as used for the U-Boot environment header
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Variables of a U-Boot environment.
+///
+/// On disk every copy is `CRC32 | [flags] | data`, where data is a list of
+/// NUL terminated `key=value` strings ending with an empty string and the
+/// CRC covers the whole data area. The flags byte only exists when the
+/// environment is redundant and tells which copy is newer.
+pub struct UBootEnv {
+    vars: Vec<(String, String)>,
+}
+
+impl UBootEnv {
+    /// creates an empty environment
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// parses the data area of an environment copy
+    pub fn parse(data: &[u8]) -> Self {
+        let mut vars = Vec::new();
+        for entry in data.split(|&b| b == 0) {
+            if entry.is_empty() {
+                break;
+            }
+            let entry = String::from_utf8_lossy(entry);
+            match entry.split_once('=') {
+                Some((k, v)) if !k.is_empty() => vars.push((k.to_string(), v.to_string())),
+                _ => log::warn!("Ignoring malformed U-Boot env entry: {entry:?}"),
+            }
+        }
+        Self { vars }
+    }
+
+    /// returns the value of `key`, none if not set
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v.as_str()))
+    }
+
+    /// iterates over all variables in environment order
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// sets `key` to `value`, replacing an existing assignment in place
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), UBootEnvError> {
+        if key.is_empty() || key.contains(['=', '\0']) || value.contains('\0') {
+            return Err(UBootEnvError::InvalidKey(key.to_string()));
+        }
+        match self.vars.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.vars.push((key.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+
+    /// removes `key`, returns true if it was set
+    pub fn unset(&mut self, key: &str) -> bool {
+        let before = self.vars.len();
+        self.vars.retain(|(k, _)| k != key);
+        before != self.vars.len()
+    }
+
+    /// serializes the variables into a zero padded data area of `len` bytes
+    pub fn to_data(&self, len: usize) -> Result<Vec<u8>, UBootEnvError> {
+        let mut data = Vec::with_capacity(len);
+        for (k, v) in &self.vars {
+            data.extend_from_slice(k.as_bytes());
+            data.push(b'=');
+            data.extend_from_slice(v.as_bytes());
+            data.push(0);
+        }
+        data.push(0);
+        if data.len() > len {
+            return Err(UBootEnvError::EnvFull(data.len()));
+        }
+        data.resize(len, 0);
+        Ok(data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// location of one copy of the environment, a file or a raw device
+struct EnvCopy {
+    path: PathBuf,
+    offset: u64,
+}
+
+#[derive(Debug, Clone)]
+/// Boot counting backend for U-Boot.
+///
+/// Works on the raw environment image like fw_printenv/fw_setenv with a
+/// configured path, offset and size, optionally with a redundant second
+/// copy. greenboot's boot_counter maps onto U-Boot's own boot counting:
+/// setting it arms `upgrade_available` with `bootlimit` as the number of
+/// tries, and the counter left is `bootlimit - bootcount`. boot_success and
+/// greenboot_rollback_trigger are stored as plain variables.
+pub struct UBoot {
+    primary: EnvCopy,
+    redundant: Option<EnvCopy>,
+    size: usize,
+}
+
+impl Default for UBoot {
+    fn default() -> Self {
+        Self::new(rooted(UBOOT_ENV_PATH), UBOOT_ENV_SIZE)
+    }
+}
+
+/// environment as read from disk, with the copy it came from
+struct LoadedEnv {
+    env: UBootEnv,
+    /// index of the active copy, 1 is the redundant one
+    active: usize,
+    flags: u8,
+}
+
+impl UBoot {
+    /// backend for a single environment copy of `size` bytes at `path`
+    pub fn new(path: impl Into<PathBuf>, size: usize) -> Self {
+        Self {
+            primary: EnvCopy {
+                path: path.into(),
+                offset: 0,
+            },
+            redundant: None,
+            size,
+        }
+    }
+
+    /// starts the environment at `offset` bytes into the file or device
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.primary.offset = offset;
+        self
+    }
+
+    /// adds a redundant copy at `offset` in `path`, which may be the primary path
+    pub fn with_redundant(mut self, path: impl Into<PathBuf>, offset: u64) -> Self {
+        self.redundant = Some(EnvCopy {
+            path: path.into(),
+            offset,
+        });
+        self
+    }
+
+    /// size of the CRC (and flags) header of each copy
+    fn header_len(&self) -> usize {
+        if self.redundant.is_some() { 5 } else { 4 }
+    }
+
+    fn copies(&self) -> impl Iterator<Item = &EnvCopy> {
+        std::iter::once(&self.primary).chain(self.redundant.as_ref())
+    }
+
+    fn read_copy(&self, copy: &EnvCopy) -> Result<Vec<u8>, UBootEnvError> {
+        let display = copy.path.display().to_string();
+        let read_err = |e| UBootEnvError::ReadFailed(display.clone(), e);
+        let mut file = File::open(&copy.path).map_err(read_err)?;
+        file.seek(SeekFrom::Start(copy.offset)).map_err(read_err)?;
+        let mut raw = vec![0; self.size];
+        file.read_exact(&mut raw).map_err(read_err)?;
+        Ok(raw)
+    }
+
+    /// Reads the newest copy with a valid CRC.
+    ///
+    /// With redundancy the flags byte decides, a higher value is newer and
+    /// 0 follows 255, matching fw_env.
+    fn load(&self) -> Result<LoadedEnv, UBootEnvError> {
+        let header = self.header_len();
+        let mut valid = Vec::new();
+        for (index, copy) in self.copies().enumerate() {
+            let raw = match self.read_copy(copy) {
+                Ok(raw) => raw,
+                Err(e) if self.redundant.is_some() => {
+                    log::warn!("{e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let crc = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+            if crc32(&raw[header..]) != crc {
+                log::warn!(
+                    "U-Boot env copy {} at offset {:#x} has a bad CRC",
+                    copy.path.display(),
+                    copy.offset
+                );
+                continue;
+            }
+            let flags = if header == 5 { raw[4] } else { 0 };
+            valid.push((index, flags, raw));
+        }
+
+        let newest = match valid.as_slice() {
+            [] => {
+                return Err(UBootEnvError::BadCrc(
+                    self.primary.path.display().to_string(),
+                ));
+            }
+            [only] => only,
+            [first, second] => {
+                let second_newer = match (first.1, second.1) {
+                    (255, 0) => true,
+                    (0, 255) => false,
+                    (a, b) => b > a,
+                };
+                if second_newer { second } else { first }
+            }
+            _ => unreachable!("at most two environment copies"),
+        };
+        Ok(LoadedEnv {
+            env: UBootEnv::parse(&newest.2[header..]),
+            active: newest.0,
+            flags: newest.1,
+        })
+    }
+
+    /// Writes `env` over the copy that is not active.
+    ///
+    /// With a redundant environment the active copy stays valid until the
+    /// new one is completely written and synced, so an interrupted write
+    /// falls back to the previous state.
+    fn store(&self, env: &UBootEnv, loaded: &LoadedEnv) -> Result<(), UBootEnvError> {
+        let header = self.header_len();
+        let data = env.to_data(self.size - header)?;
+        let mut raw = Vec::with_capacity(self.size);
+        raw.extend_from_slice(&crc32(&data).to_le_bytes());
+        let target = match &self.redundant {
+            Some(redundant) => {
+                raw.push(loaded.flags.wrapping_add(1));
+                if loaded.active == 0 {
+                    redundant
+                } else {
+                    &self.primary
+                }
+            }
+            None => &self.primary,
+        };
+        raw.extend_from_slice(&data);
+
+        let display = target.path.display().to_string();
+        let write_err = |e| UBootEnvError::WriteFailed(display.clone(), e);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&target.path)
+            .map_err(write_err)?;
+        file.seek(SeekFrom::Start(target.offset))
+            .map_err(write_err)?;
+        file.write_all(&raw).map_err(write_err)?;
+        file.sync_all().map_err(write_err)?;
+        Ok(())
+    }
+
+    /// returns the current environment variables
+    pub fn read_env(&self) -> Result<UBootEnv, UBootEnvError> {
+        Ok(self.load()?.env)
+    }
+}
+
+impl BootStateStore for UBoot {
+    fn name(&self) -> &'static str {
+        "u-boot"
+    }
+
+    fn get_boot_counter(&self) -> Result<Option<i32>> {
+        let env = self.read_env()?;
+        if env.get("upgrade_available") != Some("1") {
+            return Ok(None);
+        }
+        let parse = |key: &str| -> Result<i32> {
+            let value = env.get(key).unwrap_or("0");
+            value
+                .parse::<i32>()
+                .map_err(|_| anyhow::anyhow!("{key} has invalid value: {value}"))
+        };
+        Ok(Some(parse("bootlimit")? - parse("bootcount")?))
+    }
+
+    fn get_rollback_trigger(&self) -> Result<bool> {
+        Ok(self.read_env()?.get("greenboot_rollback_trigger") == Some("1"))
+    }
+
+    fn get_boot_success(&self) -> Result<Option<bool>> {
+        Ok(self.read_env()?.get("boot_success").map(|v| v == "1"))
+    }
+
+    fn storage_path(&self) -> Option<PathBuf> {
+        Some(self.primary.path.clone())
+    }
+
+    /// boot_counter is translated to U-Boot's bootlimit/bootcount/upgrade_available
+    fn commit(&self, state: BootState) -> Result<()> {
+        if state.is_empty() {
+            return Ok(());
+        }
+        let loaded = self.load()?;
+        let mut env = loaded.env.clone();
+        // U-Boot has no indeterminate boots to settle
+        let ops: Vec<&BootStateOp> = state
+            .ops()
+            .iter()
+            .filter(|op| op.key() != "boot_indeterminate")
+            .collect();
+        for op in &ops {
+            match op {
+                BootStateOp::Set(key, value) if key == "boot_counter" => {
+                    env.set("bootlimit", value)?;
+                    env.set("bootcount", "0")?;
+                    env.set("upgrade_available", "1")?;
+                }
+                BootStateOp::Unset(key) if key == "boot_counter" => {
+                    env.set("bootcount", "0")?;
+                    env.set("upgrade_available", "0")?;
+                }
+                BootStateOp::Set(key, value) => env.set(key, value)?,
+                BootStateOp::Unset(key) => {
+                    env.unset(key);
+                }
+            }
+        }
+        self.store(&env, &loaded)?;
+
+        for op in ops {
+            match op {
+                BootStateOp::Set(key, value) => log::info!("Set U-Boot env: {key}={value}"),
+                BootStateOp::Unset(key) => log::info!("Clear U-Boot env: {key}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// parses a decimal or `0x` prefixed hexadecimal number as used in fw_env.config
+pub fn parse_env_number(value: &str) -> Option<u64> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u64>().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tempfile::{TempDir, tempdir};
+
+    const TEST_SIZE: usize = 0x400;
+
+    /// writes an environment copy with the given vars and flags byte at `offset`
+    fn write_image(path: &Path, offset: u64, vars: &[(&str, &str)], flags: Option<u8>) {
+        let mut env = UBootEnv::new();
+        for (k, v) in vars {
+            env.set(k, v).unwrap();
+        }
+        let header = if flags.is_some() { 5 } else { 4 };
+        let data = env.to_data(TEST_SIZE - header).unwrap();
+        let mut raw = crc32(&data).to_le_bytes().to_vec();
+        raw.extend(flags);
+        raw.extend_from_slice(&data);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&raw).unwrap();
+    }
+
+    fn setup_single(vars: &[(&str, &str)]) -> (TempDir, UBoot) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("uboot.env");
+        write_image(&path, 0, vars, None);
+        (dir, UBoot::new(path, TEST_SIZE))
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_read_env() {
+        let (_dir, uboot) = setup_single(&[("bootcmd", "run distro_bootcmd"), ("bootlimit", "3")]);
+        let env = uboot.read_env().unwrap();
+        assert_eq!(env.get("bootcmd"), Some("run distro_bootcmd"));
+        assert_eq!(env.vars().count(), 2);
+        assert_eq!(uboot.get_boot_counter().unwrap(), None);
+    }
+
+    #[test]
+    fn test_boot_counter_mapping() {
+        let (_dir, uboot) = setup_single(&[("bootcmd", "boot")]);
+        uboot.set_boot_counter(3).unwrap();
+        let env = uboot.read_env().unwrap();
+        assert_eq!(env.get("bootlimit"), Some("3"));
+        assert_eq!(env.get("bootcount"), Some("0"));
+        assert_eq!(env.get("upgrade_available"), Some("1"));
+        assert_eq!(env.get("bootcmd"), Some("boot"));
+        assert_eq!(uboot.get_boot_counter().unwrap(), Some(3));
+        assert!(uboot.set_boot_counter(5).is_err());
+
+        // U-Boot counts the boots up
+        let (_dir, uboot) = setup_single(&[
+            ("bootlimit", "3"),
+            ("bootcount", "2"),
+            ("upgrade_available", "1"),
+        ]);
+        assert_eq!(uboot.get_boot_counter().unwrap(), Some(1));
+
+        uboot
+            .commit(
+                BootState::new()
+                    .set_boot_status(true)
+                    .settle_indeterminate(),
+            )
+            .unwrap();
+        let env = uboot.read_env().unwrap();
+        assert_eq!(env.get("upgrade_available"), Some("0"));
+        assert_eq!(env.get("boot_success"), Some("1"));
+        assert_eq!(env.get("boot_indeterminate"), None);
+        assert_eq!(uboot.get_boot_counter().unwrap(), None);
+
+        // a counter that cannot be read is overwritten
+        let (_dir, uboot) = setup_single(&[("bootlimit", "foo"), ("upgrade_available", "1")]);
+        assert!(uboot.get_boot_counter().is_err());
+        uboot.set_boot_counter(3).unwrap();
+        assert_eq!(uboot.get_boot_counter().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_rollback_trigger() {
+        let (_dir, uboot) = setup_single(&[]);
+        assert!(!uboot.get_rollback_trigger().unwrap());
+        uboot.set_rollback_trigger().unwrap();
+        assert!(uboot.get_rollback_trigger().unwrap());
+        uboot.unset_rollback_trigger().unwrap();
+        assert!(!uboot.get_rollback_trigger().unwrap());
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let (dir, uboot) = setup_single(&[("bootlimit", "3")]);
+        let path = dir.path().join("uboot.env");
+        let mut raw = std::fs::read(&path).unwrap();
+        raw[10] ^= 0xff;
+        std::fs::write(&path, raw).unwrap();
+        assert!(matches!(uboot.read_env(), Err(UBootEnvError::BadCrc(_))));
+    }
+
+    #[test]
+    fn test_env_full() {
+        let mut env = UBootEnv::new();
+        env.set("filler", &"x".repeat(TEST_SIZE)).unwrap();
+        assert!(matches!(
+            env.to_data(TEST_SIZE - 4),
+            Err(UBootEnvError::EnvFull(_))
+        ));
+    }
+
+    #[test]
+    fn test_redundant_env() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("uboot.env");
+        let offset = TEST_SIZE as u64;
+        write_image(&path, 0, &[("bootlimit", "1")], Some(4));
+        write_image(&path, offset, &[("bootlimit", "2")], Some(5));
+        let uboot = UBoot::new(&path, TEST_SIZE).with_redundant(&path, offset);
+
+        // the copy with the higher flags is newer
+        assert_eq!(uboot.read_env().unwrap().get("bootlimit"), Some("2"));
+
+        // writes go to the inactive copy, leaving the previous state intact
+        uboot.set_rollback_trigger().unwrap();
+        assert!(uboot.get_rollback_trigger().unwrap());
+        let raw = std::fs::read(&path).unwrap();
+        assert_eq!(raw[4], 6);
+        assert_eq!(raw[TEST_SIZE + 4], 5);
+
+        // a corrupted copy is ignored
+        let mut raw = raw;
+        raw[20] ^= 0xff;
+        std::fs::write(&path, raw).unwrap();
+        assert!(!uboot.get_rollback_trigger().unwrap());
+    }
+
+    #[test]
+    fn test_redundant_flags_wrap() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("uboot.env");
+        write_image(&path, 0, &[("bootlimit", "1")], Some(255));
+        write_image(&path, TEST_SIZE as u64, &[("bootlimit", "2")], Some(0));
+        let uboot = UBoot::new(&path, TEST_SIZE).with_redundant(&path, TEST_SIZE as u64);
+        assert_eq!(uboot.read_env().unwrap().get("bootlimit"), Some("2"));
+    }
+
+    #[test]
+    fn test_offset_and_numbers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mmcblk0");
+        write_image(&path, 0x2000, &[("bootlimit", "3")], None);
+        let uboot = UBoot::new(&path, TEST_SIZE).with_offset(0x2000);
+        assert_eq!(uboot.read_env().unwrap().get("bootlimit"), Some("3"));
+
+        assert_eq!(parse_env_number("0x2000"), Some(0x2000));
+        assert_eq!(parse_env_number("16384"), Some(16384));
+        assert_eq!(parse_env_number("nope"), None);
+    }
+}
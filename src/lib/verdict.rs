@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::greenboot::CheckResult;
+use crate::sysroot::rooted;
+
+/// where the outcome of the last health check is kept across boots
+pub static VERDICT_PATH: &str = "/var/lib/greenboot/last-verdict.json";
+
+/// set by a manual rollback, reported as fallback by the next boot
+pub static ROLLBACK_RECORD_PATH: &str = "/var/lib/greenboot/rollback.json";
+
+/// identifies the running boot, changes on every boot. Always read from the
+/// running kernel, even with a sysroot set.
+static BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+#[derive(Debug, Clone, PartialEq)]
+/// Outcome of a health check run, persisted for `greenboot status`
+pub struct Verdict {
+    /// true if all required checks passed
+    pub passed: bool,
+    /// seconds since the epoch the health check finished at
+    pub timestamp: u64,
+    /// boot the health check ran in
+    pub boot_id: Option<String>,
+    /// error reported by a failed health check
+    pub message: Option<String>,
+    /// outcome of every required and wanted check
+    pub checks: Vec<CheckResult>,
+}
+
+impl Verdict {
+    /// verdict of a health check that finished just now in the running boot
+    pub fn new(passed: bool, message: Option<String>) -> Self {
+        Self {
+            passed,
+            timestamp: now(),
+            boot_id: current_boot_id(),
+            message,
+            checks: vec![],
+        }
+    }
+
+    /// the verdict with the outcome of the individual checks
+    pub fn with_checks(mut self, checks: Vec<CheckResult>) -> Self {
+        self.checks = checks;
+        self
+    }
+
+    /// true if the verdict was recorded during the running boot
+    pub fn is_current_boot(&self) -> bool {
+        self.boot_id.is_some() && self.boot_id == current_boot_id()
+    }
+
+    /// JSON representation, as stored on disk
+    pub fn to_json(&self) -> Value {
+        json!({
+            "passed": self.passed,
+            "timestamp": self.timestamp,
+            "boot_id": self.boot_id,
+            "message": self.message,
+            "checks": self.checks.iter().map(CheckResult::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    /// parses the JSON representation, none if required fields are missing
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let string = |key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+        Some(Self {
+            passed: value.get("passed")?.as_bool()?,
+            timestamp: value.get("timestamp")?.as_u64()?,
+            boot_id: string("boot_id"),
+            message: string("message"),
+            // absent in verdicts of older versions
+            checks: value
+                .get("checks")
+                .and_then(Value::as_array)
+                .map(|checks| checks.iter().filter_map(CheckResult::from_json).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A rollback done by `greenboot rollback`, outside the health check
+pub struct RollbackRecord {
+    /// seconds since the epoch the rollback ran at
+    pub timestamp: u64,
+    /// boot the rollback ran in
+    pub boot_id: Option<String>,
+    /// deployment manager that did the rollback
+    pub manager: String,
+    /// deployment made default
+    pub target: String,
+}
+
+impl RollbackRecord {
+    /// record of a rollback done just now in the running boot
+    pub fn new(manager: &str, target: &str) -> Self {
+        Self {
+            timestamp: now(),
+            boot_id: current_boot_id(),
+            manager: manager.to_string(),
+            target: target.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp,
+            "boot_id": self.boot_id,
+            "manager": self.manager,
+            "target": self.target,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let string = |key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+        Some(Self {
+            timestamp: value.get("timestamp")?.as_u64()?,
+            boot_id: string("boot_id"),
+            manager: string("manager")?,
+            target: string("target")?,
+        })
+    }
+}
+
+/// remembers a manual rollback for the next boot
+pub fn save_rollback_record(record: &RollbackRecord) -> Result<()> {
+    write_json_at(&record.to_json(), &rooted(ROLLBACK_RECORD_PATH))
+}
+
+/// Returns a manual rollback done in an earlier boot and forgets it, so only
+/// the first boot after the rollback reports it. A rollback of the running
+/// boot is kept for the next one.
+pub fn take_rollback_record() -> Result<Option<RollbackRecord>> {
+    take_rollback_record_at(&rooted(ROLLBACK_RECORD_PATH))
+}
+
+fn take_rollback_record_at(path: &Path) -> Result<Option<RollbackRecord>> {
+    let Some(value) = read_json_at(path)? else {
+        return Ok(None);
+    };
+    let record = RollbackRecord::from_json(&value)
+        .with_context(|| format!("Invalid rollback record in {}", path.display()))?;
+    if record.boot_id.is_some() && record.boot_id == current_boot_id() {
+        return Ok(None);
+    }
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(Some(record))
+}
+
+/// atomically writes `value` to `path`, creating its directory
+fn write_json_at(value: &Value, path: &Path) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    writeln!(tmp, "{value}")?;
+    tmp.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// reads JSON from `path`, none if it does not exist
+fn read_json_at(path: &Path) -> Result<Option<Value>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    serde_json::from_str(&raw)
+        .map(Some)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn current_boot_id() -> Option<String> {
+    fs::read_to_string(BOOT_ID_PATH)
+        .ok()
+        .map(|id| id.trim().to_string())
+}
+
+/// persists the verdict of the last health check
+pub fn save_verdict(verdict: &Verdict) -> Result<()> {
+    save_verdict_at(verdict, &rooted(VERDICT_PATH))
+}
+
+fn save_verdict_at(verdict: &Verdict, path: &Path) -> Result<()> {
+    write_json_at(&verdict.to_json(), path)
+}
+
+/// loads the verdict of the last health check, none if there was none yet
+pub fn load_verdict() -> Result<Option<Verdict>> {
+    load_verdict_at(&rooted(VERDICT_PATH))
+}
+
+fn load_verdict_at(path: &Path) -> Result<Option<Verdict>> {
+    let Some(value) = read_json_at(path)? else {
+        return Ok(None);
+    };
+    Verdict::from_json(&value)
+        .map(Some)
+        .with_context(|| format!("Invalid verdict in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("greenboot/last-verdict.json");
+        assert_eq!(load_verdict_at(&path).unwrap(), None);
+
+        let verdict =
+            Verdict::new(false, Some("required check failed".to_string())).with_checks(vec![
+                CheckResult {
+                    name: String::from("01_dns.sh"),
+                    kind: crate::CheckKind::Required,
+                    status: crate::CheckStatus::Skipped,
+                    message: Some(String::from("exit code 77")),
+                    attempts: 1,
+                    perfdata: vec![],
+                    hint: None,
+                },
+            ]);
+        save_verdict_at(&verdict, &path).unwrap();
+        assert_eq!(load_verdict_at(&path).unwrap(), Some(verdict.clone()));
+        assert_eq!(verdict.is_current_boot(), verdict.boot_id.is_some());
+
+        fs::write(&path, "{\"passed\": true}").unwrap();
+        assert!(load_verdict_at(&path).is_err());
+    }
+
+    #[test]
+    fn test_rollback_record_reported_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rollback.json");
+        assert_eq!(take_rollback_record_at(&path).unwrap(), None);
+
+        // Recorded in this boot, kept for the next one
+        let record = RollbackRecord::new("bootc", "quay.io/example/os:v1");
+        write_json_at(&record.to_json(), &path).unwrap();
+        if record.boot_id.is_some() {
+            assert_eq!(take_rollback_record_at(&path).unwrap(), None);
+        }
+
+        let earlier = RollbackRecord {
+            boot_id: Some(String::from("earlier-boot")),
+            ..record
+        };
+        write_json_at(&earlier.to_json(), &path).unwrap();
+        assert_eq!(take_rollback_record_at(&path).unwrap(), Some(earlier));
+        assert!(!path.exists());
+    }
+}
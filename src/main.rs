@@ -5,11 +5,20 @@ use clap::{Parser, Subcommand, ValueEnum};
 use config::{Config, File, FileFormat};
 use greenboot::detect_os_deployment;
 use greenboot::{
-    get_boot_counter, get_rollback_trigger, handle_motd, handle_reboot, handle_rollback,
-    run_diagnostics, run_green, run_red, set_boot_counter, set_boot_status, set_rollback_trigger,
-    unset_boot_counter, unset_rollback_trigger,
+    BootCountState, BootState, BootStateOp, BootStateStore, CheckPolicy, CheckStatus,
+    DEFAULT_CHECK_PARALLELISM, DEFAULT_CHECK_TIMEOUT, Diagnostics, Finding, Grub, RollbackRecord,
+    Severity, SystemdBoot, UBOOT_ENV_PATH, UBOOT_ENV_SIZE, UBoot, Verdict, doctor, find_checks,
+    format_duration, handle_motd, handle_reboot, handle_rollback, has_sysroot, list_checks,
+    load_verdict, names_with, parse_env_number, parse_plugin_unknown, parse_retry_override,
+    parse_timeout, parse_timeout_override, repair_grubenv, rollback_plan, rooted, run_check,
+    run_diagnostics, run_green, run_red, run_rollback, save_rollback_record, save_verdict,
+    set_sysroot, summarize, sysroot, take_rollback_record,
 };
-use greenboot::{is_boot_rw, remount_boot_ro, remount_boot_rw};
+use greenboot::{find_mount_point, is_mount_rw, remount_ro, remount_rw};
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{process::Command, sync::OnceLock};
 
 /// greenboot config path
@@ -22,6 +31,9 @@ static GREENBOOT_CONFIG_FILE: &str = "/etc/greenboot/greenboot.conf";
 struct Cli {
     #[clap(value_enum, short, long, default_value_t = LogLevel::Info)]
     log_level: LogLevel,
+    /// resolve all paths below this directory, e.g. an unbooted image tree
+    #[clap(long, global = true, value_name = "DIR")]
+    root: Option<PathBuf>,
     #[clap(subcommand)]
     command: Commands,
 }
@@ -30,18 +42,25 @@ struct Cli {
 struct GreenbootConfig {
     max_reboot: u16,
     disabled_healthchecks: Vec<String>,
+    check_policy: CheckPolicy,
+    bootloader: Box<dyn BootStateStore>,
 }
 
 impl GreenbootConfig {
-    pub fn get_config() -> Self {
+    pub fn get_config() -> Result<Self> {
         let mut config = Self {
             max_reboot: 3,                 // Default value
             disabled_healthchecks: vec![], //empty list
+            check_policy: CheckPolicy::default(),
+            bootloader: Box::new(Grub::default()),
         };
 
         // Try to load from config file
         if let Ok(parsed_config) = Config::builder()
-            .add_source(File::new(GREENBOOT_CONFIG_FILE, FileFormat::Ini))
+            .add_source(File::new(
+                &rooted(GREENBOOT_CONFIG_FILE).to_string_lossy(),
+                FileFormat::Ini,
+            ))
             .build()
         {
             config.max_reboot = match parsed_config.get_int("GREENBOOT_MAX_BOOT_ATTEMPTS") {
@@ -63,9 +82,148 @@ impl GreenbootConfig {
                     vec![]
                 }
             };
+
+            config.check_policy = check_policy_from_config(&parsed_config);
+            config.bootloader = bootloader_from_config(&parsed_config)?;
+        } else {
+            config.bootloader = discover_grub(None)?;
+        }
+
+        Ok(config)
+    }
+}
+/// reads the timeouts, parallelism and retries checks run with
+fn check_policy_from_config(parsed_config: &Config) -> CheckPolicy {
+    let mut policy = CheckPolicy::default();
+    let timeouts = &mut policy.timeouts;
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_TIMEOUT") {
+        match parse_timeout(raw.trim_matches('"')) {
+            Some(timeout) => timeouts.default = timeout,
+            None => log::warn!(
+                "GREENBOOT_CHECK_TIMEOUT has invalid value '{raw}', using {}",
+                format_duration(DEFAULT_CHECK_TIMEOUT)
+            ),
+        }
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_TIMEOUTS") {
+        for entry in parse_bash_array_string(&raw) {
+            match parse_timeout_override(&entry) {
+                Some((name, timeout)) => {
+                    timeouts.overrides.insert(name, timeout);
+                }
+                None => {
+                    log::warn!("GREENBOOT_CHECK_TIMEOUTS has invalid entry '{entry}', ignoring it")
+                }
+            }
+        }
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_PARALLELISM") {
+        match raw.trim_matches('"').parse::<usize>() {
+            Ok(parallelism) if parallelism > 0 => policy.parallelism = parallelism,
+            _ => log::warn!(
+                "GREENBOOT_CHECK_PARALLELISM has invalid value '{raw}', using {DEFAULT_CHECK_PARALLELISM}"
+            ),
+        }
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_INDEPENDENT_CHECKS") {
+        policy.independent = parse_bash_array_string(&raw);
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_RETRIES") {
+        for entry in parse_bash_array_string(&raw) {
+            match parse_retry_override(&entry) {
+                Some((name, retry)) => {
+                    policy.retries.insert(name, retry);
+                }
+                None => {
+                    log::warn!("GREENBOOT_CHECK_RETRIES has invalid entry '{entry}', ignoring it")
+                }
+            }
         }
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_PLUGIN_UNKNOWN") {
+        match parse_plugin_unknown(raw.trim_matches('"')) {
+            Some(status) => policy.plugin_unknown = status,
+            None => log::warn!("GREENBOOT_PLUGIN_UNKNOWN has invalid value '{raw}', using warning"),
+        }
+    }
+    policy
+}
 
-        config
+/// builds the boot state store for the bootloader selected by GREENBOOT_BOOTLOADER
+fn bootloader_from_config(parsed_config: &Config) -> Result<Box<dyn BootStateStore>> {
+    let get = |key: &str| {
+        parsed_config
+            .get_string(key)
+            .ok()
+            .map(|v| v.trim_matches('"').to_string())
+    };
+    let number = |key: &str| {
+        get(key).and_then(|raw| {
+            let parsed = parse_env_number(&raw);
+            if parsed.is_none() {
+                log::warn!("{key} has invalid value '{raw}', ignoring it");
+            }
+            parsed
+        })
+    };
+
+    let name = get("GREENBOOT_BOOTLOADER").unwrap_or_else(|| {
+        log::debug!("GREENBOOT_BOOTLOADER not found in config, using default: grub");
+        String::from("grub")
+    });
+    match name.as_str() {
+        "grub" | "grub2" => discover_grub(get("GREENBOOT_GRUBENV_PATH").as_deref()),
+        "systemd-boot" => {
+            let mut systemd_boot = match get("GREENBOOT_ESP_PATH") {
+                Some(esp) => SystemdBoot::new(rooted(esp)),
+                None => SystemdBoot::default(),
+            };
+            if let Some(entry) = get("GREENBOOT_SYSTEMD_BOOT_ENTRY") {
+                systemd_boot = systemd_boot.with_entry(rooted(entry));
+            }
+            Ok(Box::new(systemd_boot))
+        }
+        "u-boot" | "uboot" => {
+            let size = number("GREENBOOT_UBOOT_ENV_SIZE")
+                .map(|size| size as usize)
+                .filter(|&size| size > 5)
+                .unwrap_or(UBOOT_ENV_SIZE);
+            let path = rooted(
+                get("GREENBOOT_UBOOT_ENV_PATH")
+                    .as_deref()
+                    .unwrap_or(UBOOT_ENV_PATH),
+            );
+            let mut uboot = UBoot::new(&path, size);
+            if let Some(offset) = number("GREENBOOT_UBOOT_ENV_OFFSET") {
+                uboot = uboot.with_offset(offset);
+            }
+            if let Some(offset) = number("GREENBOOT_UBOOT_ENV_REDUND_OFFSET") {
+                let redund_path = get("GREENBOOT_UBOOT_ENV_REDUND_PATH").map_or(path, rooted);
+                uboot = uboot.with_redundant(redund_path, offset);
+            }
+            Ok(Box::new(uboot))
+        }
+        other => {
+            log::warn!("unsupported bootloader: {other}, using default bootloader grub");
+            discover_grub(get("GREENBOOT_GRUBENV_PATH").as_deref())
+        }
+    }
+}
+
+/// GRUB store on the discovered grubenv, falling back to the default path.
+/// There is no fallback on a sysroot, where the default path may well be a
+/// link the image's layout does not resolve.
+fn discover_grub(override_path: Option<&str>) -> Result<Box<dyn BootStateStore>> {
+    match Grub::discover(override_path.map(Path::new)) {
+        Ok(grub) => Ok(Box::new(grub)),
+        Err(e) if has_sysroot() => Err(e.context(format!(
+            "cannot find the grubenv of {}",
+            sysroot().display()
+        ))),
+        Err(e) => {
+            log::warn!("{e}, using default grubenv");
+            Ok(Box::new(Grub::default()))
+        }
     }
 }
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -98,9 +256,66 @@ impl LogLevel {
 /// greenboot health-check -> runs the custom health checks
 ///
 /// greenboot set-rollback-trigger -> sets rollback trigger flag for next boot
+///
+/// greenboot repair-grubenv -> rewrites a corrupt grubenv as a clean block
+///
+/// greenboot status -> shows the boot state and the last health check verdict
+///
+/// greenboot list-checks -> lists the discovered checks and hooks
+///
+/// greenboot run-check <name> -> runs a single required or wanted check
+///
+/// greenboot mark-good -> marks the boot good without running the checks
+///
+/// greenboot mark-bad --reason <text> -> marks the boot bad, like a failed required check
+///
+/// greenboot rollback -> rolls back to the previous deployment after confirmation
+///
+/// greenboot doctor -> tests how greenboot is integrated with the system
 enum Commands {
-    HealthCheck,
+    HealthCheck {
+        /// run the checks, but only log what would be changed on the system
+        #[clap(long)]
+        dry_run: bool,
+    },
     SetRollbackTrigger,
+    RepairGrubenv,
+    Status {
+        /// print machine readable JSON
+        #[clap(long)]
+        json: bool,
+    },
+    ListChecks {
+        /// print machine readable JSON
+        #[clap(long)]
+        json: bool,
+    },
+    RunCheck {
+        /// file name of the check, as shown by list-checks
+        name: String,
+    },
+    MarkGood,
+    Rollback {
+        /// roll back without asking for confirmation
+        #[clap(long)]
+        yes: bool,
+        /// reboot into the previous deployment after rolling back
+        #[clap(long)]
+        reboot: bool,
+        /// only print which deployment would become default
+        #[clap(long)]
+        dry_run: bool,
+    },
+    MarkBad {
+        /// why the boot is bad, recorded in the verdict and the journal
+        #[clap(long)]
+        reason: String,
+    },
+    Doctor {
+        /// print machine readable JSON
+        #[clap(long)]
+        json: bool,
+    },
 }
 
 /// Determine if we're executing inside a containerized environment.
@@ -128,8 +343,9 @@ fn running_in_container() -> bool {
     })
 }
 
-/// Execute a mutating GRUB operation while ensuring /boot is temporarily remounted RW if needed
-fn with_boot_rw<F>(f: F) -> Result<()>
+/// Execute a mutating bootloader operation while ensuring the filesystem holding
+/// the boot state is temporarily remounted RW if needed
+fn with_boot_rw<F>(bootloader: &dyn BootStateStore, f: F) -> Result<()>
 where
     F: FnOnce() -> Result<()>,
 {
@@ -137,27 +353,46 @@ where
         log::info!("Container environment detected; skipping /boot remounts");
         return f();
     }
+    if has_sysroot() {
+        log::info!(
+            "Working on {}; skipping /boot remounts",
+            sysroot().display()
+        );
+        return f();
+    }
 
-    let was_rw =
-        is_boot_rw().map_err(|e| anyhow::anyhow!("Failed to check boot mount state: {}", e))?;
+    let mount_point = bootloader
+        .storage_path()
+        .and_then(|path| match find_mount_point(&path) {
+            Ok(mount_point) => Some(mount_point),
+            Err(e) => {
+                log::warn!("Cannot find mount point of {}: {e}", path.display());
+                None
+            }
+        })
+        .unwrap_or_else(|| PathBuf::from("/boot"));
+    let mount = mount_point.display();
+
+    let was_rw = is_mount_rw(&mount_point)
+        .map_err(|e| anyhow::anyhow!("Failed to check {} mount state: {}", mount, e))?;
 
     log::info!(
-        "Initial /boot mount state: {}",
+        "Initial {mount} mount state: {}",
         if was_rw { "rw" } else { "ro" }
     );
 
     if !was_rw {
-        log::info!("Remounting /boot as rw for operation");
-        remount_boot_rw().context("Failed to remount /boot as rw")?;
+        log::info!("Remounting {mount} as rw for operation");
+        remount_rw(&mount_point).with_context(|| format!("Failed to remount {mount} as rw"))?;
     } else {
-        log::info!("/boot is already rw; no remount needed");
+        log::info!("{mount} is already rw; no remount needed");
     }
 
     let op_result = f();
 
     if !was_rw {
-        log::info!("Restoring /boot mount to ro");
-        remount_boot_ro().context("Failed to remount /boot as ro")?;
+        log::info!("Restoring {mount} mount to ro");
+        remount_ro(&mount_point).with_context(|| format!("Failed to remount {mount} as ro"))?;
     }
 
     op_result
@@ -167,7 +402,11 @@ where
 fn check_previous_rollback() -> Result<bool> {
     log::debug!("Checking journalctl for previous rollback attempts...");
 
-    let output = Command::new("journalctl")
+    let mut journalctl = Command::new("journalctl");
+    if has_sysroot() {
+        journalctl.arg(format!("--root={}", sysroot().display()));
+    }
+    let output = journalctl
         .arg("-b")
         .arg("-1")
         .arg("-u")
@@ -203,8 +442,16 @@ fn check_previous_rollback() -> Result<bool> {
 
 /// Generate appropriate MOTD message with optional fallback prefix
 /// Generate MOTD message using pre-checked rollback status
-fn generate_motd_message(base_msg: &str, previous_rollback: bool) -> Result<String> {
-    let prefix = if previous_rollback {
+fn generate_motd_message(
+    base_msg: &str,
+    previous_rollback: bool,
+    fell_back: bool,
+) -> Result<String> {
+    let prefix = if fell_back {
+        String::from(
+            "FALLBACK BOOT DETECTED! Boot counter ran out and the bootloader booted the fallback entry.\n",
+        )
+    } else if previous_rollback {
         match detect_os_deployment() {
             Some(manager) => {
                 format!(
@@ -219,144 +466,780 @@ fn generate_motd_message(base_msg: &str, previous_rollback: bool) -> Result<Stri
     Ok(format!("{prefix}{base_msg}"))
 }
 
-/// triggers the diagnostics followed by the action on the outcome
-/// this also handles setting the grub variables and system restart
-fn health_check() -> Result<()> {
-    let config = GreenbootConfig::get_config();
-    log::debug!("{config:?}");
-
-    let container_mode = running_in_container();
-    if container_mode {
-        log::info!("Container environment detected; skipping reboot and rollback handling");
-    }
+/// Side effects of a health check, only logged in dry-run mode
+struct Actions<'a> {
+    bootloader: &'a dyn BootStateStore,
+    dry_run: bool,
+}
 
-    // Check rollback status with graceful error handling
-    let previous_rollback = match check_previous_rollback() {
-        Ok(status) => {
-            if status {
-                match detect_os_deployment() {
-                    Some(manager) => log::info!(
-                        "FALLBACK BOOT DETECTED! Default {manager} deployment has been rolled back."
-                    ),
-                    None => log::info!(
-                        "FALLBACK BOOT DETECTED! Cannot rollback as its available only on rpm-ostree or bootc system."
+impl Actions<'_> {
+    /// commits the boot state with the boot filesystem remounted rw
+    fn commit(&self, state: BootState) -> Result<()> {
+        if self.dry_run {
+            for op in state.ops() {
+                match op {
+                    BootStateOp::Set(key, value) => log::info!(
+                        "Dry run: would set {} {key}={value}",
+                        self.bootloader.name()
                     ),
+                    BootStateOp::Unset(key) => {
+                        log::info!("Dry run: would clear {} {key}", self.bootloader.name())
+                    }
                 }
             }
-            status
+            return Ok(());
         }
-        Err(e) => {
-            log::warn!("Failed to check previous rollback status: {e}. Defaulting to false.");
-            false
+        with_boot_rw(self.bootloader, || self.bootloader.commit(state))
+    }
+
+    fn motd(&self, msg: &str) -> Result<()> {
+        if self.dry_run {
+            log::info!("Dry run: would write motd: {msg:?}");
+            return Ok(());
         }
-    };
+        handle_motd(msg)
+    }
 
-    // Rest of the function remains the same...
-    handle_motd(&generate_motd_message(
-        "Greenboot healthcheck is in progress",
-        previous_rollback,
-    )?)?;
+    fn save_verdict(&self, verdict: &Verdict) {
+        if self.dry_run {
+            log::info!("Dry run: would save verdict: {}", verdict.to_json());
+            return;
+        }
+        save_verdict(verdict)
+            .unwrap_or_else(|e| log::error!("cannot save health check verdict: {e:#}"));
+    }
 
-    match run_diagnostics(config.disabled_healthchecks) {
-        Ok(_) => {
-            log::info!("greenboot health-check passed.");
-            let errors = run_green();
-            if !errors.is_empty() {
-                log::error!("There is a problem with green script runner");
-                errors.iter().for_each(|e| log::error!("{e}"));
+    /// runs the green.d or red.d hooks
+    fn run_hooks(&self, name: &str, runner: impl FnOnce() -> Vec<Box<dyn std::error::Error>>) {
+        if self.dry_run {
+            log::info!("Dry run: would run {name}.d scripts");
+            return;
+        }
+        let errors = runner();
+        if !errors.is_empty() {
+            log::error!("There is a problem with {name} script runner");
+            errors.iter().for_each(|e| log::error!("{e}"));
+        }
+    }
+
+    fn reboot(&self, force: bool) -> Result<()> {
+        if self.dry_run {
+            log::info!("Dry run: would reboot the system");
+            return Ok(());
+        }
+        handle_reboot(self.bootloader, force)
+    }
+
+    fn rollback(&self) -> Result<()> {
+        if self.dry_run {
+            match detect_os_deployment() {
+                Some(manager) => log::info!("Dry run: would run '{manager} rollback'"),
+                None => log::info!("Dry run: no deployment manager to roll back with"),
             }
+            return Ok(());
+        }
+        handle_rollback(self.bootloader)
+    }
+}
 
-            handle_motd(&generate_motd_message(
-                "Greenboot healthcheck passed - status is GREEN",
-                previous_rollback,
-            )?)
-            .unwrap_or_else(|e| log::error!("cannot set motd: {e}"));
+/// What is known about the running boot before acting on a verdict
+struct BootContext {
+    container_mode: bool,
+    /// greenboot rolled back the deployment in the previous boot
+    previous_rollback: bool,
+    /// the bootloader booted the fallback entry after the counter ran out
+    fell_back: bool,
+}
 
-            if !container_mode {
-                with_boot_rw(|| set_boot_status(true))?;
+impl BootContext {
+    /// `dry_run` leaves the record of a manual rollback for the real health check
+    fn detect(bootloader: &dyn BootStateStore, dry_run: bool) -> Self {
+        let container_mode = running_in_container();
+        if container_mode {
+            log::info!("Container environment detected; skipping reboot and rollback handling");
+        }
 
-                // Unset rollback trigger on successful health check
-                if get_rollback_trigger().unwrap_or(false) {
-                    with_boot_rw(unset_rollback_trigger)
-                        .unwrap_or_else(|e| log::error!("Failed to unset rollback trigger: {e}"));
+        // Check rollback status with graceful error handling
+        let previous_rollback = match check_previous_rollback() {
+            Ok(status) => {
+                if status {
+                    match detect_os_deployment() {
+                        Some(manager) => log::info!(
+                            "FALLBACK BOOT DETECTED! Default {manager} deployment has been rolled back."
+                        ),
+                        None => log::info!(
+                            "FALLBACK BOOT DETECTED! Cannot rollback as its available only on rpm-ostree or bootc system."
+                        ),
+                    }
                 }
+                status
             }
+            Err(e) => {
+                log::warn!("Failed to check previous rollback status: {e}. Defaulting to false.");
+                false
+            }
+        };
 
-            Ok(())
+        // `greenboot rollback` runs outside of the health check unit and its journal
+        let manual_rollback = !dry_run
+            && match take_rollback_record() {
+                Ok(Some(record)) => {
+                    log::info!(
+                        "FALLBACK BOOT DETECTED! Default {} deployment has been rolled back manually to {}.",
+                        record.manager,
+                        record.target
+                    );
+                    true
+                }
+                Ok(None) => false,
+                Err(e) => {
+                    log::warn!("Failed to read manual rollback record: {e:#}");
+                    false
+                }
+            };
+        let previous_rollback = previous_rollback || manual_rollback;
+
+        // GRUB marks a fallback to the previous entry with boot_counter=-1
+        let fell_back = match bootloader.get_boot_count_state() {
+            Ok(state) => {
+                log::info!("{} boot counting state: {state}", bootloader.name());
+                if state == BootCountState::FellBack {
+                    log::warn!(
+                        "FALLBACK BOOT DETECTED! Boot counter ran out and {} booted the fallback entry.",
+                        bootloader.name()
+                    );
+                }
+                state == BootCountState::FellBack
+            }
+            Err(e) => {
+                log::warn!("Failed to read boot counting state: {e}");
+                false
+            }
+        };
+
+        Self {
+            container_mode,
+            previous_rollback,
+            fell_back,
+        }
+    }
+
+    fn motd(&self, base_msg: &str) -> Result<String> {
+        generate_motd_message(base_msg, self.previous_rollback, self.fell_back)
+    }
+}
+
+/// triggers the diagnostics followed by the action on the outcome
+/// this also handles setting the grub variables and system restart
+///
+/// with `dry_run` the checks still run, but grubenv, motd, hooks, reboot and
+/// rollback are only logged
+fn health_check(config: &GreenbootConfig, dry_run: bool) -> Result<()> {
+    log::debug!("{config:?}");
+    let bootloader = config.bootloader.as_ref();
+    let actions = Actions {
+        bootloader,
+        dry_run,
+    };
+    if dry_run {
+        log::info!("Dry run: checks run, but nothing is changed on the system");
+    }
+    let context = BootContext::detect(bootloader, dry_run);
+
+    actions.motd(&context.motd("Greenboot healthcheck is in progress")?)?;
+
+    match run_diagnostics(config.disabled_healthchecks.clone(), &config.check_policy) {
+        Ok(diagnostics) => {
+            log::info!("greenboot health-check passed.");
+            actions.run_hooks("green", || run_green(&config.check_policy));
+            mark_good(
+                &actions,
+                &context,
+                Verdict::new(true, None).with_checks(diagnostics.results.clone()),
+                &with_summary(
+                    "Greenboot healthcheck passed - status is GREEN",
+                    &diagnostics,
+                ),
+            )
         }
         Err(e) => {
             log::error!("Greenboot error: {e}");
+            let diagnostics = e.diagnostics().cloned().unwrap_or_default();
+            mark_bad(
+                config,
+                &actions,
+                &context,
+                Verdict::new(false, Some(e.to_string())).with_checks(diagnostics.results.clone()),
+                &with_summary("Greenboot healthcheck failed - status is RED", &diagnostics),
+            )?;
+            bail!("greenboot healthcheck failed")
+        }
+    }
+}
 
-            handle_motd(&generate_motd_message(
-                "Greenboot healthcheck failed - status is RED",
-                previous_rollback,
-            )?)
-            .unwrap_or_else(|e| log::error!("cannot set motd: {e}"));
-            let errors = run_red();
-            if !errors.is_empty() {
-                log::error!("There is a problem with red script runner");
-                errors.iter().for_each(|e| log::error!("{e}"));
-            }
+/// `motd` followed by the failed and skipped checks, if any
+fn with_summary(motd: &str, diagnostics: &Diagnostics) -> String {
+    let summary = diagnostics.summary();
+    if summary.is_empty() {
+        motd.to_string()
+    } else {
+        format!("{motd}\n{summary}")
+    }
+}
 
-            if !container_mode {
-                with_boot_rw(|| set_boot_status(false))
-                    .unwrap_or_else(|e| log::error!("cannot set boot_status: {e}"));
+/// records a good boot: verdict, motd, and boot_success with the counter and
+/// rollback trigger cleared in one write
+fn mark_good(actions: &Actions, context: &BootContext, verdict: Verdict, motd: &str) -> Result<()> {
+    actions.save_verdict(&verdict);
 
-                // Check if boot_counter is 0 (exhausted retries) or if no counter is set
-                match get_boot_counter()? {
-                    Some(counter) if counter > 0 => {
-                        // Still have retries left, just reboot
-                        log::info!("Boot counter is {counter}, rebooting to try again");
-                        handle_reboot(false).unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
-                    }
-                    Some(_) => {
-                        // Boot counter reached 0 (or negative) - check rollback trigger
-                        if get_rollback_trigger().unwrap_or(false) {
-                            log::info!(
-                                "Boot counter exhausted and rollback trigger is set - initiating rollback"
-                            );
-                            match handle_rollback() {
-                                Ok(()) => {
-                                    log::info!("Rollback successful");
-                                    with_boot_rw(|| {
-                                        unset_boot_counter()?;
-                                        unset_rollback_trigger()?;
-                                        Ok(())
-                                    })
-                                    .unwrap_or_else(|e| {
-                                        log::error!("Failed to clear grub vars: {e}")
-                                    });
-                                    handle_reboot(true)
-                                        .unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
-                                }
-                                Err(rollback_err) => {
-                                    log::error!("Rollback failed: {rollback_err}");
-                                    bail!("Manual intervention required - rollback failed");
-                                }
-                            }
-                        } else {
-                            log::warn!(
-                                "Boot counter exhausted but no rollback trigger set - manual intervention required"
-                            );
-                            bail!("Manual intervention required - no rollback trigger");
+    actions
+        .motd(&context.motd(motd)?)
+        .unwrap_or_else(|e| log::error!("cannot set motd: {e}"));
+
+    if !context.container_mode {
+        // Mark the boot good, settle an indeterminate one and drop the
+        // rollback trigger in one write
+        actions.commit(
+            BootState::new()
+                .set_boot_status(true)
+                .settle_indeterminate()
+                .unset_rollback_trigger(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// records a bad boot and runs the red.d hooks, then reboots to try again or
+/// rolls back once the boot counter ran out
+fn mark_bad(
+    config: &GreenbootConfig,
+    actions: &Actions,
+    context: &BootContext,
+    verdict: Verdict,
+    motd: &str,
+) -> Result<()> {
+    let bootloader = actions.bootloader;
+    actions.save_verdict(&verdict);
+
+    actions
+        .motd(&context.motd(motd)?)
+        .unwrap_or_else(|e| log::error!("cannot set motd: {e}"));
+    actions.run_hooks("red", || run_red(&config.check_policy));
+
+    if context.container_mode {
+        return Ok(());
+    }
+
+    // GRUB does not count down boot attempts with an unreadable env,
+    // rebooting would retry the broken deployment forever
+    if let Err(e) = bootloader.verify() {
+        log::error!("{e:#}");
+        bail!("Manual intervention required - boot state is unreadable");
+    }
+
+    // The fallback entry is what a rollback would boot, rolling back
+    // again would switch to the deployment that failed before
+    if context.fell_back {
+        actions
+            .commit(BootState::new().set_boot_status(false))
+            .unwrap_or_else(|e| log::error!("cannot set boot_status: {e}"));
+        log::error!("Fallback entry failed the health check as well, not rolling back");
+        bail!("Manual intervention required - fallback boot is unhealthy");
+    }
+
+    let boot_counter = bootloader.get_boot_counter();
+
+    // Record the failed boot, and on the first failure arm the counter, in one write
+    let mut boot_state = BootState::new().set_boot_status(false);
+    if let Ok(None) = boot_counter {
+        boot_state = boot_state.set_boot_counter(config.max_reboot);
+    }
+    actions
+        .commit(boot_state)
+        .unwrap_or_else(|e| log::error!("cannot set boot_status: {e}"));
+
+    // Check if boot_counter is 0 (exhausted retries) or if no counter is set
+    match boot_counter? {
+        Some(counter) if counter > 0 => {
+            // Still have retries left, just reboot
+            log::info!("Boot counter is {counter}, rebooting to try again");
+            actions
+                .reboot(false)
+                .unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
+        }
+        Some(_) => {
+            // Boot counter reached 0 (or negative) - check rollback trigger
+            if bootloader.get_rollback_trigger().unwrap_or(false) {
+                log::info!(
+                    "Boot counter exhausted and rollback trigger is set - initiating rollback"
+                );
+                match actions.rollback() {
+                    Ok(()) => {
+                        // greenboot checks the journal for this message next boot
+                        if !actions.dry_run {
+                            log::info!("Rollback successful");
                         }
+                        actions
+                            .commit(
+                                BootState::new()
+                                    .unset_boot_counter()
+                                    .unset_rollback_trigger(),
+                            )
+                            .unwrap_or_else(|e| log::error!("Failed to clear grub vars: {e}"));
+                        actions
+                            .reboot(true)
+                            .unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
                     }
-                    None => {
-                        // No boot counter set - this is the first failure, set it and reboot
-                        log::info!(
-                            "First health check failure, setting boot counter to {}",
-                            config.max_reboot
-                        );
-                        with_boot_rw(|| set_boot_counter(config.max_reboot))
-                            .unwrap_or_else(|e| log::error!("cannot set boot_counter: {e}"));
-                        handle_reboot(false).unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
+                    Err(rollback_err) => {
+                        log::error!("Rollback failed: {rollback_err}");
+                        bail!("Manual intervention required - rollback failed");
                     }
                 }
+            } else {
+                log::warn!(
+                    "Boot counter exhausted but no rollback trigger set - manual intervention required"
+                );
+                bail!("Manual intervention required - no rollback trigger");
+            }
+        }
+        None => {
+            // No boot counter set - this is the first failure, it was armed above
+            log::info!(
+                "First health check failure, boot counter set to {}",
+                config.max_reboot
+            );
+            actions
+                .reboot(false)
+                .unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
+        }
+    }
+
+    Ok(())
+}
+
+/// turns a failed read into none, logging why the value is unknown
+fn or_log<T>(what: &str, result: Result<T>) -> Option<T> {
+    result
+        .map_err(|e| log::warn!("Cannot read {what}: {e:#}"))
+        .ok()
+}
+
+/// human readable age of a unix timestamp
+fn format_age(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let age = now.saturating_sub(timestamp);
+    match age {
+        0..60 => format!("{age}s ago"),
+        60..3600 => format!("{}m ago", age / 60),
+        3600..86400 => format!("{}h {}m ago", age / 3600, age % 3600 / 60),
+        _ => format!("{}d {}h ago", age / 86400, age % 86400 / 3600),
+    }
+}
+
+/// prints the effective timeouts, parallelism, retries and plugin handling
+fn print_check_policy(policy: &CheckPolicy) {
+    let timeout =
+        |timeout: Option<Duration>| timeout.map_or(String::from("no limit"), format_duration);
+    let mut overrides: Vec<String> = policy
+        .timeouts
+        .overrides
+        .iter()
+        .map(|(name, limit)| format!("{name}: {}", timeout(*limit)))
+        .collect();
+    overrides.sort();
+    println!(
+        "Check timeout:      {}{}",
+        timeout(policy.timeouts.default),
+        if overrides.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", overrides.join(", "))
+        }
+    );
+    println!("Check parallelism:  {}", policy.parallelism);
+    println!(
+        "Independent checks: {}",
+        if policy.independent.is_empty() {
+            String::from("none")
+        } else {
+            policy.independent.join(", ")
+        }
+    );
+    let mut retries: Vec<String> = policy
+        .retries
+        .iter()
+        .map(|(name, retry)| {
+            format!(
+                "{name}: {} attempts, {} delay, backoff {}",
+                retry.attempts,
+                format_duration(retry.delay),
+                retry.backoff
+            )
+        })
+        .collect();
+    retries.sort();
+    println!(
+        "Check retries:      {}",
+        if retries.is_empty() {
+            String::from("none")
+        } else {
+            retries.join(", ")
+        }
+    );
+    println!("Plugin UNKNOWN:     {}", policy.plugin_unknown);
+}
+
+/// prints boot state, deployment manager, effective config and the last verdict
+fn status(config: &GreenbootConfig, as_json: bool) -> Result<()> {
+    let bootloader = config.bootloader.as_ref();
+    let boot_counter = or_log("boot_counter", bootloader.get_boot_counter());
+    let boot_count_state = or_log("boot count state", bootloader.get_boot_count_state());
+    let boot_success = or_log("boot_success", bootloader.get_boot_success()).flatten();
+    let rollback_trigger = or_log("rollback trigger", bootloader.get_rollback_trigger());
+    let storage_path = bootloader.storage_path();
+    let deployment_manager = detect_os_deployment();
+    let verdict = or_log("last health check verdict", load_verdict()).flatten();
+
+    if as_json {
+        let mut effective_config = config.check_policy.to_json();
+        effective_config["max_boot_attempts"] = json!(config.max_reboot);
+        effective_config["disabled_healthchecks"] = json!(config.disabled_healthchecks);
+        effective_config["bootloader"] = json!(bootloader.name());
+        let status = json!({
+            "bootloader": bootloader.name(),
+            "storage_path": storage_path,
+            "boot_counter": boot_counter.flatten(),
+            "boot_count_state": boot_count_state.map(|state| state.to_string()),
+            "boot_success": boot_success,
+            "rollback_trigger": rollback_trigger,
+            "deployment_manager": deployment_manager,
+            "config": effective_config,
+            "last_verdict": verdict.as_ref().map(|verdict| {
+                let mut value = verdict.to_json();
+                value["current_boot"] = Value::Bool(verdict.is_current_boot());
+                value
+            }),
+        });
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    let unknown = || String::from("unknown");
+    let yes_no = |value: Option<bool>| match value {
+        Some(true) => String::from("yes"),
+        Some(false) => String::from("no"),
+        None => unknown(),
+    };
+    println!(
+        "Bootloader:         {}{}",
+        bootloader.name(),
+        storage_path
+            .map(|path| format!(" ({})", path.display()))
+            .unwrap_or_default()
+    );
+    println!(
+        "Boot counter:       {}",
+        match boot_counter {
+            Some(Some(counter)) => counter.to_string(),
+            Some(None) => String::from("unset"),
+            None => unknown(),
+        }
+    );
+    println!(
+        "Boot count state:   {}",
+        boot_count_state.map_or_else(unknown, |state| state.to_string())
+    );
+    println!("Boot success:       {}", yes_no(boot_success));
+    println!("Rollback trigger:   {}", yes_no(rollback_trigger));
+    println!(
+        "Deployment manager: {}",
+        deployment_manager.unwrap_or("none")
+    );
+    println!("Max boot attempts:  {}", config.max_reboot);
+    println!(
+        "Disabled checks:    {}",
+        if config.disabled_healthchecks.is_empty() {
+            String::from("none")
+        } else {
+            config.disabled_healthchecks.join(", ")
+        }
+    );
+    print_check_policy(&config.check_policy);
+    match verdict {
+        Some(verdict) => {
+            println!(
+                "Last health check:  {} {}, {}",
+                if verdict.passed { "GREEN" } else { "RED" },
+                format_age(verdict.timestamp),
+                if verdict.is_current_boot() {
+                    "this boot"
+                } else {
+                    "previous boot"
+                }
+            );
+            if let Some(message) = &verdict.message {
+                println!("                    {message}");
             }
+            if !verdict.checks.is_empty() {
+                let count = |status| names_with(&verdict.checks, status).len();
+                println!(
+                    "Checks:             {} passed, {} failed, {} timed out, {} with warnings, {} skipped, {} disabled",
+                    count(CheckStatus::Passed),
+                    count(CheckStatus::Failed),
+                    count(CheckStatus::TimedOut),
+                    count(CheckStatus::Warning),
+                    count(CheckStatus::Skipped),
+                    count(CheckStatus::Disabled)
+                );
+                for line in summarize(&verdict.checks).lines() {
+                    println!("                    {line}");
+                }
+            }
+        }
+        None => println!("Last health check:  never"),
+    }
+    Ok(())
+}
 
-            bail!("greenboot healthcheck failed")
+/// tests that the config file parses and holds usable values
+fn check_config() -> Finding {
+    let path = rooted(GREENBOOT_CONFIG_FILE);
+    if !path.exists() {
+        return Finding::pass(
+            "config",
+            format!("{} not found, using defaults", path.display()),
+        );
+    }
+    let parsed_config = match Config::builder()
+        .add_source(File::new(&path.to_string_lossy(), FileFormat::Ini))
+        .build()
+    {
+        Ok(parsed_config) => parsed_config,
+        Err(e) => {
+            return Finding::fail(
+                "config",
+                format!("cannot parse {}: {e}", path.display()),
+                "fix the file, greenboot ignores all of it until then",
+            );
+        }
+    };
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_MAX_BOOT_ATTEMPTS")
+        && raw.trim_matches('"').parse::<u16>().is_err()
+    {
+        return Finding::warn(
+            "config",
+            format!("GREENBOOT_MAX_BOOT_ATTEMPTS has invalid value '{raw}', using 3"),
+            "set GREENBOOT_MAX_BOOT_ATTEMPTS to a number",
+        );
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_BOOTLOADER") {
+        let name = raw.trim_matches('"');
+        if !matches!(name, "grub" | "grub2" | "systemd-boot" | "u-boot" | "uboot") {
+            return Finding::warn(
+                "config",
+                format!("GREENBOOT_BOOTLOADER has unsupported value '{name}', using grub"),
+                "set GREENBOOT_BOOTLOADER to grub, systemd-boot or u-boot",
+            );
         }
     }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_TIMEOUT")
+        && parse_timeout(raw.trim_matches('"')).is_none()
+    {
+        return Finding::warn(
+            "config",
+            format!(
+                "GREENBOOT_CHECK_TIMEOUT has invalid value '{raw}', using {}",
+                format_duration(DEFAULT_CHECK_TIMEOUT)
+            ),
+            "set GREENBOOT_CHECK_TIMEOUT to seconds like 300, 30s, 5m or 1h, 0 or infinity for no limit",
+        );
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_TIMEOUTS")
+        && let Some(entry) = parse_bash_array_string(&raw)
+            .into_iter()
+            .find(|entry| parse_timeout_override(entry).is_none())
+    {
+        return Finding::warn(
+            "config",
+            format!("GREENBOOT_CHECK_TIMEOUTS has invalid entry '{entry}', ignoring it"),
+            "write entries as \"name:timeout\", e.g. \"01_update_platforms_check.sh:60s\"",
+        );
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_RETRIES")
+        && let Some(entry) = parse_bash_array_string(&raw)
+            .into_iter()
+            .find(|entry| parse_retry_override(entry).is_none())
+    {
+        return Finding::warn(
+            "config",
+            format!("GREENBOOT_CHECK_RETRIES has invalid entry '{entry}', ignoring it"),
+            "write entries as \"name:attempts[:delay[:backoff]]\"",
+        );
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_CHECK_PARALLELISM")
+        && !raw.trim_matches('"').parse::<usize>().is_ok_and(|n| n > 0)
+    {
+        return Finding::warn(
+            "config",
+            format!(
+                "GREENBOOT_CHECK_PARALLELISM has invalid value '{raw}', using {DEFAULT_CHECK_PARALLELISM}"
+            ),
+            "set GREENBOOT_CHECK_PARALLELISM to a positive number",
+        );
+    }
+    if let Ok(raw) = parsed_config.get_string("GREENBOOT_PLUGIN_UNKNOWN")
+        && parse_plugin_unknown(raw.trim_matches('"')).is_none()
+    {
+        return Finding::warn(
+            "config",
+            format!("GREENBOOT_PLUGIN_UNKNOWN has invalid value '{raw}', using warning"),
+            "set GREENBOOT_PLUGIN_UNKNOWN to warning, critical or skip",
+        );
+    }
+    Finding::pass("config", format!("{} is valid", path.display()))
+}
+
+/// prints the doctor report, fails if any finding failed
+fn run_doctor(config: &GreenbootConfig, as_json: bool) -> Result<()> {
+    let mut findings = vec![check_config()];
+    findings.extend(doctor(
+        config.bootloader.as_ref(),
+        &config.disabled_healthchecks,
+    ));
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let failed = count(Severity::Fail);
+
+    if as_json {
+        let report = json!({
+            "findings": findings.iter().map(Finding::to_json).collect::<Vec<_>>(),
+            "passed": count(Severity::Pass),
+            "warnings": count(Severity::Warn),
+            "failed": failed,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for finding in &findings {
+            println!(
+                "{:<4}  {:<10}  {}",
+                finding.severity.as_str().to_uppercase(),
+                finding.check,
+                finding.message
+            );
+            if let Some(hint) = &finding.hint {
+                println!("{:<18}hint: {hint}", "");
+            }
+        }
+        println!(
+            "\n{} passed, {} warnings, {failed} failed",
+            count(Severity::Pass),
+            count(Severity::Warn)
+        );
+    }
+    if failed > 0 {
+        bail!("greenboot doctor found {failed} problems");
+    }
+    Ok(())
+}
+
+/// prints every discovered check and hook and whether it would run
+fn print_checks(config: &GreenbootConfig, as_json: bool) -> Result<()> {
+    let inventory = list_checks(&config.disabled_healthchecks);
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&inventory.to_json())?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<9} {:<20} {:<7} {:<16} NAME",
+        "TYPE", "SOURCE", "RUNNER", "STATUS"
+    );
+    for entry in &inventory.entries {
+        let status = if entry.disabled {
+            "disabled"
+        } else if entry.masked {
+            "masked"
+        } else if entry.lacks_exec_bit() {
+            "skipped: no exec"
+        } else {
+            "enabled"
+        };
+        println!(
+            "{:<9} {:<20} {:<7} {:<16} {}{}",
+            entry.kind.as_str(),
+            entry.source.display(),
+            entry.runner.as_str(),
+            status,
+            entry.name,
+            entry
+                .overrides
+                .as_ref()
+                .map(|vendor| format!(" (overrides {})", vendor.display()))
+                .unwrap_or_default()
+        );
+    }
+    if !inventory.unmatched_disabled.is_empty() {
+        println!(
+            "\nDISABLED_HEALTHCHECKS entries matching no check: {}",
+            inventory.unmatched_disabled.join(", ")
+        );
+    }
+    for problem in &inventory.problems {
+        println!("configuration error: {problem}");
+    }
+    Ok(())
+}
+
+/// asks `question` on the terminal, true if answered with yes
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// operator initiated rollback, previews the deployment becoming default first
+fn rollback(config: &GreenbootConfig, yes: bool, reboot: bool, dry_run: bool) -> Result<()> {
+    let bootloader = config.bootloader.as_ref();
+    let plan = rollback_plan()?;
+    println!("Deployment manager: {}", plan.manager);
+    println!("Current default:    {}", plan.current);
+    println!("Rollback target:    {}", plan.target);
+    if dry_run {
+        println!("Dry run: nothing was changed");
+        return Ok(());
+    }
+    if !yes && !confirm("Make the rollback target the default deployment?")? {
+        bail!("Rollback cancelled");
+    }
+
+    run_rollback(plan.manager)?;
+    log::info!("Rolled back to {}", plan.target);
+    save_rollback_record(&RollbackRecord::new(plan.manager, &plan.target))
+        .unwrap_or_else(|e| log::error!("cannot record rollback: {e:#}"));
+
+    // The counter and trigger belong to the deployment rolled away from
+    if !running_in_container() {
+        with_boot_rw(bootloader, || {
+            bootloader.commit(
+                BootState::new()
+                    .unset_boot_counter()
+                    .unset_rollback_trigger(),
+            )
+        })
+        .unwrap_or_else(|e| log::error!("Failed to clear grub vars: {e}"));
+    }
+
+    if reboot {
+        handle_reboot(bootloader, true)?;
+    } else {
+        println!("Reboot to boot {}", plan.target);
+    }
+    Ok(())
 }
 
 // This function parses a string expected in bash-array format like
@@ -397,18 +1280,289 @@ fn main() -> Result<()> {
     pretty_env_logger::formatted_builder()
         .filter_level(cli.log_level.to_log())
         .init();
+    if let Some(root) = &cli.root {
+        set_sysroot(root)?;
+    }
 
     match cli.command {
-        Commands::HealthCheck => health_check(),
+        Commands::HealthCheck { dry_run } => {
+            let config = GreenbootConfig::get_config()?;
+            health_check(&config, dry_run)
+        }
         Commands::SetRollbackTrigger => {
             if running_in_container() {
                 log::info!("Container environment detected; skipping rollback trigger updates");
                 return Ok(());
             }
             log::info!("Setting rollback trigger for next boot...");
-            with_boot_rw(set_rollback_trigger)?;
+            let config = GreenbootConfig::get_config()?;
+            with_boot_rw(config.bootloader.as_ref(), || {
+                config.bootloader.set_rollback_trigger()
+            })?;
             log::info!("Rollback trigger set successfully.");
             Ok(())
         }
+        Commands::MarkGood => {
+            let config = GreenbootConfig::get_config()?;
+            let bootloader = config.bootloader.as_ref();
+            log::info!("Boot marked good manually");
+            let actions = Actions {
+                bootloader,
+                dry_run: false,
+            };
+            mark_good(
+                &actions,
+                &BootContext::detect(bootloader, false),
+                Verdict::new(true, Some(String::from("marked good manually"))),
+                "Boot marked good manually - status is GREEN",
+            )
+        }
+        Commands::Rollback {
+            yes,
+            reboot,
+            dry_run,
+        } => {
+            let config = GreenbootConfig::get_config()?;
+            rollback(&config, yes, reboot, dry_run)
+        }
+        Commands::MarkBad { reason } => {
+            let config = GreenbootConfig::get_config()?;
+            let bootloader = config.bootloader.as_ref();
+            log::error!("Boot marked bad: {reason}");
+            let actions = Actions {
+                bootloader,
+                dry_run: false,
+            };
+            mark_bad(
+                &config,
+                &actions,
+                &BootContext::detect(bootloader, false),
+                Verdict::new(false, Some(format!("marked bad: {reason}"))),
+                &format!("Boot marked bad: {reason} - status is RED"),
+            )
+        }
+        Commands::RunCheck { name } => {
+            let checks = find_checks(&name);
+            if checks.is_empty() {
+                bail!("no required or wanted check named {name}");
+            }
+            let config = GreenbootConfig::get_config()?;
+            let mut failed = 0;
+            let mut statuses = vec![];
+            for check in &checks {
+                if config.disabled_healthchecks.contains(&check.name) {
+                    log::warn!(
+                        "{name} is disabled through DISABLED_HEALTHCHECKS, running it anyway"
+                    );
+                }
+                match run_check(check, &config.check_policy) {
+                    Ok(status) => statuses.push(status),
+                    Err(e) => {
+                        log::error!("{e:#}");
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                bail!("{failed} of {} checks named {name} failed", checks.len());
+            }
+            if statuses.contains(&CheckStatus::Warning) {
+                println!("{name}: warning");
+            } else if statuses.contains(&CheckStatus::Passed) {
+                println!("{name}: passed");
+            } else {
+                println!("{name}: skipped");
+            }
+            Ok(())
+        }
+        Commands::ListChecks { json } => {
+            let config = GreenbootConfig::get_config()?;
+            print_checks(&config, json)
+        }
+        Commands::Doctor { json } => {
+            let config = GreenbootConfig::get_config()?;
+            run_doctor(&config, json)
+        }
+        Commands::Status { json } => {
+            let config = GreenbootConfig::get_config()?;
+            status(&config, json)
+        }
+        Commands::RepairGrubenv => {
+            let config = GreenbootConfig::get_config()?;
+            let bootloader = config.bootloader.as_ref();
+            let path = match bootloader.storage_path() {
+                Some(path) if bootloader.name() == "grub" => path,
+                _ => bail!("repair-grubenv requires GREENBOOT_BOOTLOADER=grub"),
+            };
+            with_boot_rw(bootloader, || {
+                let repair = repair_grubenv(&path)?;
+                if repair.status.is_ok() {
+                    log::info!("grubenv {} is ok, nothing to repair", path.display());
+                    return Ok(());
+                }
+                log::info!(
+                    "Repaired grubenv {} ({}), kept: {:?}",
+                    path.display(),
+                    repair.status,
+                    repair.kept
+                );
+                repair
+                    .dropped
+                    .iter()
+                    .for_each(|line| log::warn!("Dropped unrecoverable grubenv line: {line:?}"));
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use greenboot::MemoryStore;
+    use tempfile::TempDir;
+
+    /// works on a scratch sysroot, where greenboot refuses to reboot or roll
+    /// back the machine running the tests
+    fn test_config() -> GreenbootConfig {
+        static ROOT: OnceLock<TempDir> = OnceLock::new();
+        ROOT.get_or_init(|| {
+            let root = tempfile::tempdir().unwrap();
+            for dir in ["etc/motd.d", "var/lib/greenboot"] {
+                std::fs::create_dir_all(root.path().join(dir)).unwrap();
+            }
+            set_sysroot(root.path()).unwrap();
+            root
+        });
+        GreenbootConfig {
+            max_reboot: 3,
+            disabled_healthchecks: vec![],
+            check_policy: CheckPolicy::default(),
+            bootloader: Box::new(MemoryStore::new()),
+        }
+    }
+
+    fn context(fell_back: bool) -> BootContext {
+        BootContext {
+            container_mode: false,
+            previous_rollback: false,
+            fell_back,
+        }
+    }
+
+    fn mark_bad_with(config: &GreenbootConfig, fell_back: bool, dry_run: bool) -> Result<()> {
+        let actions = Actions {
+            bootloader: config.bootloader.as_ref(),
+            dry_run,
+        };
+        mark_bad(
+            config,
+            &actions,
+            &context(fell_back),
+            Verdict::new(false, Some(String::from("broken"))),
+            "Greenboot healthcheck failed - status is RED",
+        )
+    }
+
+    #[test]
+    fn test_first_failure_arms_counter() {
+        let config = test_config();
+        // the reboot that follows is refused on a sysroot and only logged
+        mark_bad_with(&config, false, false).unwrap();
+        let store = config.bootloader.as_ref();
+        assert_eq!(store.get_boot_success().unwrap(), Some(false));
+        assert_eq!(store.get_boot_counter().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_exhausted_counter_rolls_back() {
+        let config = test_config();
+        let store = config.bootloader.as_ref();
+        store.set_boot_counter(0).unwrap();
+        store.set_rollback_trigger().unwrap();
+
+        // the rollback is attempted, but refused on a sysroot
+        let err = mark_bad_with(&config, false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Manual intervention required - rollback failed"
+        );
+        assert_eq!(store.get_boot_success().unwrap(), Some(false));
+        assert_eq!(store.get_boot_counter().unwrap(), Some(0));
+        assert!(store.get_rollback_trigger().unwrap());
+
+        store.unset_rollback_trigger().unwrap();
+        let err = mark_bad_with(&config, false, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Manual intervention required - no rollback trigger"
+        );
+    }
+
+    #[test]
+    fn test_failed_fallback_does_not_roll_back() {
+        let config = test_config();
+        let store = config.bootloader.as_ref();
+        store
+            .commit(
+                BootState::new()
+                    .set("boot_counter", -1)
+                    .set_rollback_trigger(),
+            )
+            .unwrap();
+
+        let err = mark_bad_with(&config, true, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Manual intervention required - fallback boot is unhealthy"
+        );
+        assert_eq!(store.get_boot_success().unwrap(), Some(false));
+        assert_eq!(store.get_boot_counter().unwrap(), Some(-1));
+        assert!(store.get_rollback_trigger().unwrap());
+    }
+
+    #[test]
+    fn test_mark_good() {
+        let config = test_config();
+        let store = config.bootloader.as_ref();
+        store.set_boot_counter(2).unwrap();
+        store.set_rollback_trigger().unwrap();
+
+        let actions = Actions {
+            bootloader: store,
+            dry_run: false,
+        };
+        mark_good(
+            &actions,
+            &context(false),
+            Verdict::new(true, None),
+            "Greenboot healthcheck passed - status is GREEN",
+        )
+        .unwrap();
+        assert_eq!(store.get_boot_success().unwrap(), Some(true));
+        assert_eq!(store.get_boot_counter().unwrap(), None);
+        assert!(!store.get_rollback_trigger().unwrap());
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let config = test_config();
+        let store = config.bootloader.as_ref();
+        mark_bad_with(&config, false, true).unwrap();
+
+        let actions = Actions {
+            bootloader: store,
+            dry_run: true,
+        };
+        mark_good(
+            &actions,
+            &context(false),
+            Verdict::new(true, None),
+            "Greenboot healthcheck passed - status is GREEN",
+        )
+        .unwrap();
+        assert_eq!(store.get_boot_success().unwrap(), None);
+        assert_eq!(store.get_boot_counter().unwrap(), None);
+        assert!(!store.get_rollback_trigger().unwrap());
     }
 }